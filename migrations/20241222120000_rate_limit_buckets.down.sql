@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS rate_limit_buckets;
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS milk_ledger;
+DROP TABLE IF EXISTS milk_stock;
\ No newline at end of file
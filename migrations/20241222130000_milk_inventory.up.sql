@@ -0,0 +1,16 @@
+-- Add up migration script here
+
+CREATE TABLE IF NOT EXISTS milk_stock (
+    id INT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+    liters DOUBLE PRECISION NOT NULL DEFAULT 0 CHECK (liters >= 0)
+);
+
+INSERT INTO milk_stock (id, liters) VALUES (1, 0) ON CONFLICT DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS milk_ledger (
+    id BIGSERIAL PRIMARY KEY,
+    operation TEXT NOT NULL,
+    liters DOUBLE PRECISION NOT NULL,
+    balance DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
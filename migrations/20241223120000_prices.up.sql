@@ -0,0 +1,6 @@
+-- Add up migration script here
+
+CREATE TABLE IF NOT EXISTS prices (
+    item TEXT PRIMARY KEY,
+    price DOUBLE PRECISION NOT NULL CHECK (price >= 0)
+);
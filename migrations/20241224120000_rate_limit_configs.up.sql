@@ -0,0 +1,9 @@
+-- Add up migration script here
+
+CREATE TABLE IF NOT EXISTS rate_limit_configs (
+    name TEXT PRIMARY KEY,
+    max_tokens BIGINT NOT NULL,
+    refill BIGINT NOT NULL,
+    interval_ms BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,187 @@
+// Offline lookups in a RustSec advisory database checkout, read from the
+// directory in `ADVISORY_DB_PATH` ("advisory-db" by default). Advisories live in
+// `crates/<name>/<id>.md`, with their metadata in a TOML block at the top. The
+// checkout is read once, on the first lookup, and kept indexed by crate.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+
+const DEFAULT_PATH: &str = "advisory-db";
+
+#[derive(Debug)]
+pub enum AdvisoryError {
+    MissingDatabase(PathBuf),
+    Io(PathBuf, String),
+    Parse(PathBuf, String),
+}
+
+impl fmt::Display for AdvisoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdvisoryError::MissingDatabase(path) => write!(f, "No advisory database at {}", path.display()),
+            AdvisoryError::Io(path, e) => write!(f, "Cannot read {}: {e}", path.display()),
+            AdvisoryError::Parse(path, e) => write!(f, "Invalid advisory {}: {e}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for AdvisoryError {}
+
+#[derive(Debug, Deserialize)]
+struct AdvisoryFile {
+    advisory: Metadata,
+    #[serde(default)]
+    versions: Versions,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    id: String,
+    package: String,
+    date: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    informational: Option<String>,
+    withdrawn: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Versions {
+    #[serde(default)]
+    patched: Vec<String>,
+    #[serde(default)]
+    unaffected: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Advisory {
+    pub id: String,
+    pub package: String,
+    pub title: Option<String>,
+    pub date: Option<String>,
+    pub url: Option<String>,
+    pub aliases: Vec<String>,
+    // Kind of informational advisory, like `unmaintained`, `None` for vulnerabilities.
+    pub informational: Option<String>,
+    pub patched: Vec<String>,
+    pub unaffected: Vec<String>,
+    withdrawn: Option<String>,
+    requirements: Vec<VersionReq>,
+}
+
+impl Advisory {
+    // Versions matching neither a patched nor an unaffected range are affected.
+    pub fn affects(&self, version: &Version) -> bool {
+        !self.requirements.iter().any(|requirement| requirement.matches(version))
+    }
+}
+
+// Splits an advisory into its TOML metadata and the Markdown after it.
+fn front_matter(source: &str) -> Option<(&str, &str)> {
+    source.trim_start().strip_prefix("```toml")?.split_once("\n```")
+}
+
+fn parse(path: &Path, source: &str) -> Result<Advisory, AdvisoryError> {
+    let (metadata, markdown) = front_matter(source)
+        .ok_or_else(|| AdvisoryError::Parse(path.to_path_buf(), "Missing TOML block".to_string()))?;
+    let file = toml::from_str::<AdvisoryFile>(metadata)
+        .map_err(|e| AdvisoryError::Parse(path.to_path_buf(), e.message().trim().to_string()))?;
+    let requirements = file.versions.patched.iter().chain(&file.versions.unaffected)
+        .map(|range| VersionReq::parse(range)
+            .map_err(|e| AdvisoryError::Parse(path.to_path_buf(), format!("Invalid version range {range}: {e}"))))
+        .collect::<Result<Vec<VersionReq>, AdvisoryError>>()?;
+    let title = markdown.lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().to_string());
+
+    Ok(Advisory {
+        id: file.advisory.id,
+        package: file.advisory.package,
+        title,
+        date: file.advisory.date,
+        url: file.advisory.url,
+        aliases: file.advisory.aliases,
+        informational: file.advisory.informational,
+        patched: file.versions.patched,
+        unaffected: file.versions.unaffected,
+        withdrawn: file.advisory.withdrawn,
+        requirements,
+    })
+}
+
+// Advisories by crate name, withdrawn ones left out.
+#[derive(Debug, Default)]
+pub struct AdvisoryIndex(HashMap<String, Vec<Advisory>>);
+
+impl AdvisoryIndex {
+    pub fn advisories(&self, name: &str) -> &[Advisory] {
+        self.0.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+fn read_dir(directory: &Path) -> Result<Vec<PathBuf>, AdvisoryError> {
+    let entries = fs::read_dir(directory).map_err(|e| AdvisoryError::Io(directory.to_path_buf(), e.to_string()))?;
+    entries.map(|entry| entry.map(|entry| entry.path()).map_err(|e| AdvisoryError::Io(directory.to_path_buf(), e.to_string())))
+        .collect()
+}
+
+fn load(path: &Path) -> Result<AdvisoryIndex, AdvisoryError> {
+    let crates = path.join("crates");
+    match fs::metadata(&crates) {
+        Ok(metadata) if metadata.is_dir() => {},
+        Ok(_) => return Err(AdvisoryError::MissingDatabase(path.to_path_buf())),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(AdvisoryError::MissingDatabase(path.to_path_buf())),
+        Err(e) => return Err(AdvisoryError::Io(crates, e.to_string())),
+    }
+
+    let mut index = AdvisoryIndex::default();
+    for directory in read_dir(&crates)?.into_iter().filter(|directory| directory.is_dir()) {
+        for path in read_dir(&directory)? {
+            if path.extension().and_then(|extension| extension.to_str()) != Some("md") {
+                continue;
+            }
+            let source = fs::read_to_string(&path).map_err(|e| AdvisoryError::Io(path.clone(), e.to_string()))?;
+            let advisory = parse(&path, &source)?;
+            if advisory.withdrawn.is_none() {
+                index.0.entry(advisory.package.clone()).or_default().push(advisory);
+            }
+        }
+    }
+    for advisories in index.0.values_mut() {
+        advisories.sort_by(|a, b| a.id.cmp(&b.id));
+    }
+    Ok(index)
+}
+
+#[derive(Debug, Clone)]
+pub struct AdvisoryDb {
+    path: PathBuf,
+    // Failed loads are not kept, the checkout may show up later.
+    index: Arc<OnceLock<AdvisoryIndex>>,
+}
+
+impl AdvisoryDb {
+    pub fn from_env() -> Self {
+        let path = std::env::var("ADVISORY_DB_PATH").unwrap_or_else(|_| DEFAULT_PATH.to_string());
+        AdvisoryDb { path: PathBuf::from(path), index: Arc::default() }
+    }
+
+    // The indexed database, read on a blocking thread the first time.
+    pub async fn index(&self) -> Result<&AdvisoryIndex, AdvisoryError> {
+        if let Some(index) = self.index.get() {
+            return Ok(index);
+        }
+        let path = self.path.clone();
+        let index = tokio::task::spawn_blocking(move || load(&path)).await
+            .map_err(|e| AdvisoryError::Io(self.path.clone(), e.to_string()))??;
+        Ok(self.index.get_or_init(|| index))
+    }
+}
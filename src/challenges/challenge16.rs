@@ -1,86 +1,310 @@
 // Challenge 16 : https://console.shuttle.dev/shuttlings/cch24/challenge/16
 
+use std::sync::OnceLock;
 use axum::{
     Router,
+    extract::{rejection::StringRejection, DefaultBodyLimit, Query},
+    handler::Handler,
     http::{header, HeaderMap, StatusCode},
-    response::{IntoResponse},
+    response::{IntoResponse, Json, Response},
     routing::{get, post},
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use rand::RngCore;
+use ring::aead;
+use mime::Mime;
+
+use crate::rate_limit::RateLimitLayer;
 
 const SECRET: &[u8] = b"santarocks";
+const SANTA_PUBLIC_KEY: &[u8] = include_bytes!("../../day16_santa_public_key.pem");
+const ISSUER: &str = "santa";
+const DEFAULT_SUBJECT: &str = "gift";
+// Gifts expire after one day unless a `ttl` is given.
+const DEFAULT_TTL: u64 = 24 * 60 * 60;
+// Largest gift body, enforced while the body is read.
+const MAX_GIFT_SIZE: usize = 2048;
+// Gifts travel in a cookie. Signing and encryption grow them by well over half,
+// so the final cookie is checked against the browser limit as well.
+const MAX_COOKIE_SIZE: usize = 4096;
+// Protected header of encrypted gifts: the signed gift token is encrypted
+// directly with a shared AES-256-GCM key.
+const JWE_HEADER: &str = r#"{"alg":"dir","enc":"A256GCM","cty":"JWT"}"#;
+// Content encryption key, read once from `GIFT_ENCRYPTION_KEY` as the base64 of
+// 32 random bytes. Gifts cannot be encrypted without one.
+static ENCRYPTION_KEY: OnceLock<Option<aead::LessSafeKey>> = OnceLock::new();
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
     sub: String,
+    iss: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
+    iat: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nbf: Option<usize>,
     exp: usize,
     data: serde_json::Value,
 }
 
 impl Claims {
-    fn new(data: serde_json::Value, exp: usize) -> Self {
+    fn new(data: serde_json::Value, params: &WrapParams) -> Self {
+        let now = chrono::Utc::now().timestamp() as usize;
+        let ttl = params.ttl.unwrap_or(DEFAULT_TTL) as usize;
         Self {
-            sub: "gift".to_string(),
-            exp,
+            sub: params.sub.clone().unwrap_or_else(|| DEFAULT_SUBJECT.to_string()),
+            iss: ISSUER.to_string(),
+            aud: params.aud.clone(),
+            iat: now,
+            nbf: params.nbf,
+            exp: now.saturating_add(ttl),
             data,
         }
     }
 }
 
-pub fn get_routes() -> Router {
+// Optional claims for a wrapped gift, from query parameters or the `Gift-TTL`,
+// `Gift-Audience`, `Gift-Not-Before` and `Gift-Subject` headers. Query
+// parameters win. `ttl` is in seconds, `nbf` is a unix timestamp.
+#[derive(Debug, Deserialize)]
+struct WrapParams {
+    ttl: Option<u64>,
+    aud: Option<String>,
+    nbf: Option<usize>,
+    sub: Option<String>,
+    #[serde(default)]
+    encrypt: bool,
+}
+
+impl WrapParams {
+    fn with_headers(mut self, headers: &HeaderMap) -> Result<Self, GiftError> {
+        let header = |name: &'static str| headers.get(name)
+            .map(|v| v.to_str().map(str::to_string).map_err(|_| GiftError::InvalidHeader(name)))
+            .transpose();
+        let number = |name: &'static str| header(name)?
+            .map(|v| v.trim().parse::<usize>().map_err(|_| GiftError::InvalidHeader(name)))
+            .transpose();
+        if self.ttl.is_none() {
+            self.ttl = number("gift-ttl")?.map(|ttl| ttl as u64);
+        }
+        if self.aud.is_none() {
+            self.aud = header("gift-audience")?;
+        }
+        if self.nbf.is_none() {
+            self.nbf = number("gift-not-before")?;
+        }
+        if self.sub.is_none() {
+            self.sub = header("gift-subject")?;
+        }
+        Ok(self)
+    }
+}
+
+// Audience expected by the service unwrapping the gift.
+#[derive(Debug, Deserialize)]
+struct UnwrapParams {
+    aud: Option<String>,
+}
+
+// Errors of the gift endpoints, each with its own status and message.
+pub enum GiftError {
+    MissingContentType,
+    InvalidContentType,
+    UnsupportedMediaType(String),
+    PayloadTooLarge,
+    CookieTooLarge(usize),
+    InvalidBody(String),
+    InvalidJson(serde_json::Error),
+    InvalidTtl,
+    InvalidHeader(&'static str),
+    JwtError(jsonwebtoken::errors::Error),
+    EncryptionUnavailable,
+    EncryptionFailed,
+    DecryptionFailed,
+    MissingCookie,
+    InvalidCookie,
+}
+
+impl IntoResponse for GiftError {
+    fn into_response(self) -> Response {
+        match self {
+            GiftError::MissingContentType => {
+                println!("ERR: MissingContentType");
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Missing content type, expected application/json".to_string())
+            },
+
+            GiftError::InvalidContentType => {
+                println!("ERR: InvalidContentType");
+                (StatusCode::BAD_REQUEST, "Invalid content type".to_string())
+            },
+
+            GiftError::UnsupportedMediaType(media_type) => {
+                println!("ERR: UnsupportedMediaType {media_type}");
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("Unsupported media type {media_type}, expected application/json"))
+            },
+
+            GiftError::PayloadTooLarge => {
+                println!("ERR: PayloadTooLarge");
+                (StatusCode::PAYLOAD_TOO_LARGE, format!("Gift is larger than {MAX_GIFT_SIZE} bytes"))
+            },
+
+            GiftError::CookieTooLarge(size) => {
+                println!("ERR: CookieTooLarge {size}");
+                (StatusCode::PAYLOAD_TOO_LARGE, format!("Wrapped gift is {size} bytes, larger than the {MAX_COOKIE_SIZE} bytes a cookie can hold"))
+            },
+
+            GiftError::InvalidBody(rejection) => {
+                println!("ERR: InvalidBody {rejection}");
+                (StatusCode::BAD_REQUEST, rejection)
+            },
+
+            GiftError::InvalidJson(rejection) => {
+                println!("{}", rejection);
+                (StatusCode::BAD_REQUEST, format!("Invalid JSON: {rejection}"))
+            },
+
+            GiftError::InvalidTtl => {
+                println!("ERR: InvalidTtl");
+                (StatusCode::BAD_REQUEST, "Invalid ttl".to_string())
+            },
+
+            GiftError::InvalidHeader(name) => {
+                println!("ERR: InvalidHeader {name}");
+                (StatusCode::BAD_REQUEST, format!("Invalid {name} header"))
+            },
+
+            GiftError::JwtError(rejection) => {
+                println!("{}", rejection);
+                (StatusCode::BAD_REQUEST, "Invalid JWT token".to_string())
+            },
+
+            GiftError::EncryptionUnavailable => {
+                println!("ERR: EncryptionUnavailable");
+                (StatusCode::NOT_IMPLEMENTED, "Gift encryption is not configured".to_string())
+            },
+
+            GiftError::EncryptionFailed => {
+                println!("ERR: EncryptionFailed");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Cannot encrypt gift".to_string())
+            },
+
+            GiftError::DecryptionFailed => {
+                println!("ERR: DecryptionFailed");
+                (StatusCode::BAD_REQUEST, "Invalid JWE token".to_string())
+            },
+
+            GiftError::MissingCookie => {
+                println!("ERR: MissingCookie");
+                (StatusCode::BAD_REQUEST, "Missing cookie".to_string())
+            },
+
+            GiftError::InvalidCookie => {
+                println!("ERR: InvalidCookie");
+                (StatusCode::BAD_REQUEST, "Invalid cookie".to_string())
+            },
+
+        }.into_response()
+    }
+}
+
+impl From<serde_json::Error> for GiftError {
+    fn from(rejection: serde_json::Error) -> Self {
+        Self::InvalidJson(rejection)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for GiftError {
+    fn from(rejection: jsonwebtoken::errors::Error) -> Self {
+        Self::JwtError(rejection)
+    }
+}
+
+pub fn get_routes(wrap_limit: RateLimitLayer) -> Router {
     
     Router::new()
-        .route("/16/wrap", post(handle_wrap))
+        .route("/16/wrap", post(handle_wrap.layer(DefaultBodyLimit::max(MAX_GIFT_SIZE))).layer(wrap_limit))
         .route("/16/unwrap", get(handle_unwrap))
         .route("/16/decode", post(handle_decode))
+        .route("/16/inspect", post(handle_inspect))
 }
 
-async fn handle_wrap(headers: HeaderMap, body: String) ->  impl IntoResponse {
+async fn handle_wrap(Query(params): Query<WrapParams>, headers: HeaderMap, body: Result<String, StringRejection>) ->  Result<Response, GiftError> {
 
-    if let Some("application/json") = headers.get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+    check_json_content_type(&headers)?;
+    let params = params.with_headers(&headers)?;
+    let body = body.map_err(|rejection| match rejection.status() {
+        StatusCode::PAYLOAD_TOO_LARGE => GiftError::PayloadTooLarge,
+        _ => GiftError::InvalidBody(rejection.body_text()),
+    })?;
+    let data: serde_json::Value = serde_json::from_str(&body)?;
+    println!("JSON Body:\n{}", data);
+    if params.ttl == Some(0) {
+        return Err(GiftError::InvalidTtl);
+    }
+    if params.encrypt && encryption_key().is_none() {
+        return Err(GiftError::EncryptionUnavailable);
+    }
 
-        let data: serde_json::Value = serde_json::from_str(&body).unwrap();
-        println!("JSON Body:\n{}", data);
-        let expire = (chrono::Utc::now() + chrono::Days::new(1)).timestamp() as usize;
-        
-        let claims = Claims::new(data, expire);
-        let Ok(token) = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(SECRET))
-        else {
-            return (StatusCode::BAD_REQUEST, "Invalid JWT token".to_string()).into_response();
-        };
-        (StatusCode::OK, [(header::SET_COOKIE, format!("gift={token}"))], body.to_string()).into_response()
+    let claims = Claims::new(data, &params);
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(SECRET))?;
+    let token = if params.encrypt {
+        encrypt_token(&token).ok_or(GiftError::EncryptionFailed)?
     } else {
-        (StatusCode::BAD_REQUEST, "NO JSON given\n").into_response()
+        token
+    };
+    let cookie = format!("gift={token}");
+    if cookie.len() > MAX_COOKIE_SIZE {
+        return Err(GiftError::CookieTooLarge(cookie.len()));
     }
+    Ok((StatusCode::OK, [(header::SET_COOKIE, cookie)], body).into_response())
 }
 
-async fn handle_unwrap(headers: HeaderMap) ->  impl IntoResponse {
+async fn handle_unwrap(Query(params): Query<UnwrapParams>, headers: HeaderMap) ->  Result<String, GiftError> {
 
-    let Some(header_value) = headers.get(header::COOKIE) else {
-        return (StatusCode::BAD_REQUEST, "Missing cookie".to_string()).into_response();
-    };
-    let Ok(cookie) = header_value.to_str() else {
-        return (StatusCode::BAD_REQUEST, "Invalid cookie".to_string()).into_response();
-    };
+    let header_value = headers.get(header::COOKIE).ok_or(GiftError::MissingCookie)?;
+    let cookie = header_value.to_str().map_err(|_| GiftError::InvalidCookie)?;
     let token: String = cookie.split("gift=").collect();
+    // Encrypted gifts carry the signed token inside.
+    let token = if is_encrypted(&token) {
+        decrypt_token(&token).ok_or(GiftError::DecryptionFailed)?
+    } else {
+        token
+    };
+    // Validate issuer, audience and time claims.
+    let mut validation = Validation::default();
+    validation.set_issuer(&[ISSUER]);
+    validation.validate_nbf = true;
+    if let Some(aud) = &params.aud {
+        validation.set_audience(&[aud]);
+    }
     // Decode the token.
-    let Ok(data) = decode::<Claims>(&token, 
+    let data = decode::<Claims>(&token, 
                                 &DecodingKey::from_secret(SECRET), 
-                                &Validation::default()) 
-    else { 
-        return (StatusCode::BAD_REQUEST, "Invalid JWT token".to_string()).into_response();
-    };
+                                &validation)?;
     //println!("{:?}", data);
-    (StatusCode::OK, data.claims.data.to_string()).into_response()
+    Ok(data.claims.data.to_string())
+}
+
+// Media types may carry parameters, e.g. `application/json; charset=utf-8`.
+fn check_json_content_type(headers: &HeaderMap) -> Result<(), GiftError> {
+    let content_type = headers.get(header::CONTENT_TYPE).ok_or(GiftError::MissingContentType)?;
+    let mime = content_type.to_str().ok()
+        .and_then(|value| value.parse::<Mime>().ok())
+        .ok_or(GiftError::InvalidContentType)?;
+    if mime.essence_str() != mime::APPLICATION_JSON.essence_str() {
+        return Err(GiftError::UnsupportedMediaType(mime.essence_str().to_string()));
+    }
+    Ok(())
 }
 
 async fn handle_decode(body: String) ->  impl IntoResponse {
-    let key = DecodingKey::from_rsa_pem(include_bytes!("../../day16_santa_public_key.pem")).unwrap();
+    let key = DecodingKey::from_rsa_pem(SANTA_PUBLIC_KEY).unwrap();
     let mut validation = Validation::default();
     validation.algorithms = vec![Algorithm::RS256, Algorithm::RS512];
     // Ignore expiration.
@@ -97,3 +321,191 @@ async fn handle_decode(body: String) ->  impl IntoResponse {
     }
 
 }
+
+// Expected claims checked by the inspection, if given.
+#[derive(Debug, Deserialize)]
+struct InspectParams {
+    aud: Option<String>,
+    iss: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Inspection {
+    encrypted: bool,
+    header: Option<Value>,
+    claims: Option<Value>,
+    algorithm: Option<Algorithm>,
+    verified_by: Option<&'static str>,
+    signature_valid: bool,
+    problems: Vec<String>,
+}
+
+// Keys known to the service, with the algorithms each one verifies.
+fn verification_keys() -> Vec<(&'static str, DecodingKey, Vec<Algorithm>)> {
+    let mut keys = vec![
+        ("gift", DecodingKey::from_secret(SECRET), vec![Algorithm::HS256, Algorithm::HS384, Algorithm::HS512]),
+    ];
+    if let Ok(key) = DecodingKey::from_rsa_pem(SANTA_PUBLIC_KEY) {
+        keys.push(("santa", key, vec![
+            Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+            Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+        ]));
+    }
+    keys
+}
+
+async fn handle_inspect(Query(params): Query<InspectParams>, body: String) -> impl IntoResponse {
+    let mut report = Inspection::default();
+    let mut token = body.trim().to_string();
+
+    if is_encrypted(&token) {
+        report.encrypted = true;
+        let Some(jws) = decrypt_token(&token) else {
+            report.problems.push("cannot decrypt token".to_string());
+            return Json(report);
+        };
+        token = jws;
+    }
+
+    let Some((message, signature)) = token.rsplit_once('.') else {
+        report.problems.push("malformed token".to_string());
+        return Json(report);
+    };
+    // Header and claims are decoded without verification so they can always be
+    // reported, even with an algorithm the service does not know.
+    let (header, payload) = message.split_once('.').unwrap_or((message, ""));
+    match decode_segment(header) {
+        Some(header) => report.header = Some(header),
+        None => report.problems.push("invalid header".to_string()),
+    }
+    match decode_segment(payload) {
+        Some(claims) => report.claims = Some(claims),
+        None => report.problems.push("invalid claims".to_string()),
+    }
+
+    // Signature.
+    let alg = report.header.as_ref()
+        .and_then(|header| header.get("alg"))
+        .map(|alg| alg.as_str().map(str::to_string).unwrap_or_else(|| alg.to_string()));
+    report.algorithm = alg.as_deref().and_then(|alg| alg.parse::<Algorithm>().ok());
+    let keys = verification_keys();
+    match (&report.header, alg, report.algorithm) {
+        // Already reported as an invalid header.
+        (None, _, _) => {},
+        (Some(_), None, _) => report.problems.push("missing algorithm".to_string()),
+        (Some(_), Some(alg), None) => report.problems.push(format!("unsupported algorithm {alg}")),
+        (Some(_), Some(_), Some(alg)) => {
+            let candidates: Vec<_> = keys.iter().filter(|(_, _, algs)| algs.contains(&alg)).collect();
+            if candidates.is_empty() {
+                report.problems.push(format!("unsupported algorithm {alg:?}"));
+            } else {
+                report.verified_by = candidates.iter()
+                    .find(|(_, key, _)| jsonwebtoken::crypto::verify(signature, message.as_bytes(), key, alg).unwrap_or(false))
+                    .map(|(name, _, _)| *name);
+                report.signature_valid = report.verified_by.is_some();
+                if !report.signature_valid {
+                    report.problems.push("invalid signature".to_string());
+                }
+            }
+        },
+    }
+
+    // Time, audience and issuer claims.
+    if let Some(claims) = &report.claims {
+        let now = chrono::Utc::now().timestamp();
+        if let Some(exp) = claims.get("exp").and_then(Value::as_i64) {
+            if exp < now {
+                report.problems.push(format!("expired at {exp}"));
+            }
+        }
+        if let Some(nbf) = claims.get("nbf").and_then(Value::as_i64) {
+            if nbf > now {
+                report.problems.push(format!("not valid before {nbf}"));
+            }
+        }
+        if let Some(expected) = &params.aud {
+            // `aud` may be a single string or a list of strings.
+            let matches = match claims.get("aud") {
+                Some(Value::String(aud)) => aud == expected,
+                Some(Value::Array(auds)) => auds.iter().any(|aud| aud == expected.as_str()),
+                _ => false,
+            };
+            if !matches {
+                report.problems.push(format!("wrong audience, expected {expected}"));
+            }
+        }
+        if let Some(expected) = &params.iss {
+            if claims.get("iss").and_then(Value::as_str) != Some(expected.as_str()) {
+                report.problems.push(format!("wrong issuer, expected {expected}"));
+            }
+        }
+    }
+    Json(report)
+}
+
+fn decode_segment(segment: &str) -> Option<Value> {
+    let json = URL_SAFE_NO_PAD.decode(segment).ok()?;
+    serde_json::from_slice::<Value>(&json).ok().filter(Value::is_object)
+}
+
+// Compact JWEs have five segments, signed JWTs have three.
+fn is_encrypted(token: &str) -> bool {
+    token.split('.').count() == 5
+}
+
+fn encryption_key() -> Option<&'static aead::LessSafeKey> {
+    ENCRYPTION_KEY.get_or_init(|| {
+        let value = std::env::var("GIFT_ENCRYPTION_KEY").ok()?;
+        let key = STANDARD.decode(value.trim()).ok()
+            .and_then(|bytes| aead::UnboundKey::new(&aead::AES_256_GCM, &bytes).ok());
+        if key.is_none() {
+            println!("ERR: GIFT_ENCRYPTION_KEY is not the base64 of 32 bytes");
+        }
+        key.map(aead::LessSafeKey::new)
+    }).as_ref()
+}
+
+fn encrypt_token(token: &str) -> Option<String> {
+    let header = URL_SAFE_NO_PAD.encode(JWE_HEADER);
+    let mut iv = [0u8; aead::NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut iv);
+
+    let mut ciphertext = token.as_bytes().to_vec();
+    let tag = encryption_key()?
+        .seal_in_place_separate_tag(
+            aead::Nonce::assume_unique_for_key(iv),
+            aead::Aad::from(header.as_bytes()),
+            &mut ciphertext,
+        )
+        .ok()?;
+    // The encrypted key segment is empty for direct encryption.
+    Some(format!(
+        "{header}..{}.{}.{}",
+        URL_SAFE_NO_PAD.encode(iv),
+        URL_SAFE_NO_PAD.encode(&ciphertext),
+        URL_SAFE_NO_PAD.encode(tag.as_ref()),
+    ))
+}
+
+fn decrypt_token(jwe: &str) -> Option<String> {
+    let segments: Vec<&str> = jwe.split('.').collect();
+    let [header, encrypted_key, iv, ciphertext, tag] = segments[..] else {
+        return None;
+    };
+    let protected: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+    if protected["alg"] != "dir" || protected["enc"] != "A256GCM" || !encrypted_key.is_empty() {
+        return None;
+    }
+
+    let iv: [u8; aead::NONCE_LEN] = URL_SAFE_NO_PAD.decode(iv).ok()?.try_into().ok()?;
+    let mut in_out = URL_SAFE_NO_PAD.decode(ciphertext).ok()?;
+    in_out.extend(URL_SAFE_NO_PAD.decode(tag).ok()?);
+    let plaintext = encryption_key()?
+        .open_in_place(
+            aead::Nonce::assume_unique_for_key(iv),
+            aead::Aad::from(header.as_bytes()),
+            &mut in_out,
+        )
+        .ok()?;
+    String::from_utf8(plaintext.to_vec()).ok()
+}
@@ -15,6 +15,8 @@ use rand::{
     distributions::{Alphanumeric, DistString},
 };
 
+use crate::rate_limit::RateLimitLayer;
+
 #[derive(Debug, FromRow, Deserialize, Serialize)]
 struct Quote {
     id: uuid::Uuid,
@@ -36,7 +38,7 @@ pub struct AppState {
     tokens: Arc<Mutex<HashMap<String, i32>>>,
 }
 
-pub fn get_routes(pool: PgPool) -> Router {
+pub fn get_routes(pool: PgPool, draft_limit: RateLimitLayer) -> Router {
     
     let tokens = Arc::new(Mutex::new(HashMap::new()));
     let state = AppState { pool, tokens };
@@ -45,7 +47,7 @@ pub fn get_routes(pool: PgPool) -> Router {
         .route("/19/cite/:id", get(handle_cite))
         .route("/19/remove/:id", delete(handle_remove))
         .route("/19/undo/:id", put(handle_undo))
-        .route("/19/draft", post(handle_draft))
+        .route("/19/draft", post(handle_draft).layer(draft_limit))
         .route("/19/list", get(handle_list))
         .with_state(state)
 }
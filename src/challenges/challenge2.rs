@@ -1,13 +1,19 @@
 // Challenge 2 : https://console.shuttle.dev/shuttlings/cch24/challenge/2
 
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use axum::{
-    response::IntoResponse,
-    routing::get,
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::{get, post},
     Router
 };
 use axum::extract::Query;
-use serde::Deserialize;
+use ipnet::{IpNet, Ipv4Net};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::ip_cipher::{self, Algorithm, CipherError};
 
 pub fn get_routes() -> Router {
     Router::new()
@@ -15,97 +21,280 @@ pub fn get_routes() -> Router {
         .route("/2/key", get(handle_key))
         .route("/2/v6/dest", get(handle_dest_v6))
         .route("/2/v6/key", get(handle_key_v6))
+        .route("/2/batch", post(handle_batch))
+        .route("/2/encrypt", get(handle_encrypt))
+        .route("/2/decrypt", get(handle_decrypt))
+}
+
+#[derive(Debug)]
+pub enum IpError {
+    MissingParameter(&'static str),
+    InvalidParameter(&'static str, String),
+    WrongFamily(&'static str, String),
+    // IPv4-mapped IPv6 addresses only make sense to the IPv4 endpoints.
+    MappedAddress(&'static str, String),
+    CipherError(CipherError),
+}
+
+impl IpError {
+    fn parameter(&self) -> Option<&'static str> {
+        match self {
+            IpError::MissingParameter(name)
+            | IpError::InvalidParameter(name, _)
+            | IpError::WrongFamily(name, _)
+            | IpError::MappedAddress(name, _) => Some(name),
+            IpError::CipherError(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for IpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpError::MissingParameter(name) => write!(f, "Missing parameter {name}"),
+            IpError::InvalidParameter(name, value) => write!(f, "Invalid {name}: {value}"),
+            IpError::WrongFamily(name, value) => write!(f, "Wrong address family for {name}: {value}"),
+            IpError::MappedAddress(name, value) => write!(f, "IPv4-mapped address not allowed for {name}: {value}"),
+            IpError::CipherError(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl IntoResponse for IpError {
+    fn into_response(self) -> Response {
+        println!("ERR: {}", self);
+        (StatusCode::BAD_REQUEST, Json(json!({"error": self.to_string(), "parameter": self.parameter()}))).into_response()
+    }
+}
+
+impl From<CipherError> for IpError {
+    fn from(rejection: CipherError) -> Self {
+        Self::CipherError(rejection)
+    }
+}
+
+fn required<'a>(value: &'a Option<String>, name: &'static str) -> Result<&'a str, IpError> {
+    value.as_deref().ok_or(IpError::MissingParameter(name))
+}
+
+fn parse_algo(value: &Option<String>) -> Result<Option<Algorithm>, IpError> {
+    value.as_deref()
+        .map(|value| value.parse().map_err(|_| IpError::InvalidParameter("algo", value.to_string())))
+        .transpose()
+}
+
+// Parses either a CIDR range or a single address, which is a range with a full-length prefix.
+fn parse_net(value: &str, name: &'static str) -> Result<IpNet, IpError> {
+    value.parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| IpError::InvalidParameter(name, value.to_string()))
+}
+
+// IPv4 inputs may also be written as IPv4-mapped IPv6 (::ffff:10.0.0.1).
+fn parse_net_v4(value: &str, name: &'static str) -> Result<IpNet, IpError> {
+    match parse_net(value, name)? {
+        net @ IpNet::V4(_) => Ok(net),
+        IpNet::V6(net) => match net.addr().to_ipv4_mapped() {
+            Some(addr) if net.prefix_len() >= 96 => Ok(Ipv4Net::new(addr, net.prefix_len() - 96).unwrap().into()),
+            _ => Err(IpError::WrongFamily(name, value.to_string())),
+        },
+    }
+}
+
+fn parse_net_v6(value: &str, name: &'static str) -> Result<IpNet, IpError> {
+    match parse_net(value, name)? {
+        IpNet::V4(_) => Err(IpError::WrongFamily(name, value.to_string())),
+        IpNet::V6(net) if net.addr().to_ipv4_mapped().is_some() => Err(IpError::MappedAddress(name, value.to_string())),
+        net => Ok(net),
+    }
+}
+
+fn parse_v4(value: &str, name: &'static str) -> Result<Ipv4Addr, IpError> {
+    match value.parse::<IpAddr>() {
+        Ok(IpAddr::V4(addr)) => Ok(addr),
+        Ok(IpAddr::V6(addr)) => addr.to_ipv4_mapped().ok_or(IpError::WrongFamily(name, value.to_string())),
+        Err(_) => Err(IpError::InvalidParameter(name, value.to_string())),
+    }
+}
+
+// Keys are bit patterns rather than addresses, so IPv4-mapped keys are fine.
+fn parse_v6_key(value: &str, name: &'static str) -> Result<Ipv6Addr, IpError> {
+    match value.parse::<IpAddr>() {
+        Ok(IpAddr::V6(addr)) => Ok(addr),
+        Ok(IpAddr::V4(_)) => Err(IpError::WrongFamily(name, value.to_string())),
+        Err(_) => Err(IpError::InvalidParameter(name, value.to_string())),
+    }
+}
+
+fn parse_v6(value: &str, name: &'static str) -> Result<Ipv6Addr, IpError> {
+    let addr = parse_v6_key(value, name)?;
+    if addr.to_ipv4_mapped().is_some() {
+        return Err(IpError::MappedAddress(name, value.to_string()));
+    }
+    Ok(addr)
+}
+
+// Full form of an address, with every IPv6 segment written out.
+fn expand(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => addr.to_string(),
+        IpAddr::V6(addr) => addr.segments().iter().map(|s| format!("{s:04x}")).collect::<Vec<String>>().join(":"),
+    }
+}
+
+// Ranges are returned in CIDR notation, single addresses as they are.
+fn format_net(net: IpNet, is_range: bool) -> String {
+    if is_range {
+        net.trunc().to_string()
+    } else {
+        net.addr().to_string()
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct DestParams {
-    from: Option<Ipv4Addr>,
-    key: Option<Ipv4Addr>,
+    from: Option<String>,
+    key: Option<String>,
+    algo: Option<String>,
 }
 
-async fn handle_dest(params: Query<DestParams>) -> impl IntoResponse {
+async fn handle_dest(params: Query<DestParams>) -> Result<String, IpError> {
     // Parse query parameters.
-    let from = params.from.unwrap_or(Ipv4Addr::new(0, 0, 0, 0));
-    let key = params.key.unwrap_or(Ipv4Addr::new(0, 0, 0, 0));
-
-    let a:u8 = from.octets().to_vec()[0].wrapping_add(key.octets().to_vec()[0]);
-    let b:u8 = from.octets().to_vec()[1].wrapping_add(key.octets().to_vec()[1]);
-    let c:u8 = from.octets().to_vec()[2].wrapping_add(key.octets().to_vec()[2]);
-    let d:u8 = from.octets().to_vec()[3].wrapping_add(key.octets().to_vec()[3]);
+    let from = required(&params.from, "from")?;
+    let from_net = parse_net_v4(from, "from")?;
+    let key = parse_v4(required(&params.key, "key")?, "key")?;
+    let algo = parse_algo(&params.algo)?.unwrap_or(Algorithm::Add);
 
-    let destination = Ipv4Addr::new(a, b, c, d);
-    destination.to_string()
+    let destination = ip_cipher::encrypt(algo, from_net, key.into())?;
+    Ok(format_net(destination, from.contains('/')))
 }
 
 #[derive(Debug, Deserialize)]
 struct KeyParams {
-    from: Option<Ipv4Addr>,
-    to: Option<Ipv4Addr>,
+    from: Option<String>,
+    to: Option<String>,
+    algo: Option<String>,
 }
 
-async fn handle_key(params: Query<KeyParams>) -> impl IntoResponse {
+async fn handle_key(params: Query<KeyParams>) -> Result<String, IpError> {
     // Parse query parameters.
-    let from = params.from.unwrap_or(Ipv4Addr::new(0, 0, 0, 0));
-    let to = params.to.unwrap_or(Ipv4Addr::new(0, 0, 0, 0));
+    let from = parse_v4(required(&params.from, "from")?, "from")?;
+    let to = parse_v4(required(&params.to, "to")?, "to")?;
+    let algo = parse_algo(&params.algo)?.unwrap_or(Algorithm::Add);
 
-    let a:u8 = to.octets().to_vec()[0].wrapping_sub(from.octets().to_vec()[0]);
-    let b:u8 = to.octets().to_vec()[1].wrapping_sub(from.octets().to_vec()[1]);
-    let c:u8 = to.octets().to_vec()[2].wrapping_sub(from.octets().to_vec()[2]);
-    let d:u8 = to.octets().to_vec()[3].wrapping_sub(from.octets().to_vec()[3]);
-
-    let key = Ipv4Addr::new(a, b, c, d);
-    key.to_string()
+    Ok(ip_cipher::key(algo, from.into(), to.into())?.to_string())
 }
 
-#[derive(Debug, Deserialize)]
-struct Destv6Params {
-    from: Option<Ipv6Addr>,
-    key: Option<Ipv6Addr>,
+async fn handle_dest_v6(params: Query<DestParams>) -> Result<String, IpError> {
+    // Parse query parameters.
+    let from = required(&params.from, "from")?;
+    let from_net = parse_net_v6(from, "from")?;
+    let key = parse_v6_key(required(&params.key, "key")?, "key")?;
+    let algo = parse_algo(&params.algo)?.unwrap_or(Algorithm::Xor);
+
+    let destination = ip_cipher::encrypt(algo, from_net, key.into())?;
+    Ok(format_net(destination, from.contains('/')))
 }
 
-async fn handle_dest_v6(params: Query<Destv6Params>) -> impl IntoResponse {
+async fn handle_key_v6(params: Query<KeyParams>) -> Result<String, IpError> {
     // Parse query parameters.
-    let from = params.from.unwrap_or(Ipv6Addr::new(0,0,0,0,0,0,0,0));
-    let key = params.key.unwrap_or(Ipv6Addr::new(0,0,0,0,0,0,0,0));
-    let from_parts = from.segments().to_vec();
-    let key_parts = key.segments().to_vec();
+    let from = parse_v6(required(&params.from, "from")?, "from")?;
+    let to = parse_v6(required(&params.to, "to")?, "to")?;
+    let algo = parse_algo(&params.algo)?.unwrap_or(Algorithm::Xor);
+
+    Ok(ip_cipher::key(algo, from.into(), to.into())?.to_string())
+}
 
-    let a:u16 = from_parts[0] ^ key_parts[0];
-    let b:u16 = from_parts[1] ^ key_parts[1];
-    let c:u16 = from_parts[2] ^ key_parts[2];
-    let d:u16 = from_parts[3] ^ key_parts[3];
-    let e:u16 = from_parts[4] ^ key_parts[4];
-    let f:u16 = from_parts[5] ^ key_parts[5];
-    let g:u16 = from_parts[6] ^ key_parts[6];
-    let h:u16 = from_parts[7] ^ key_parts[7];
+type Cipher = fn(Algorithm, IpNet, IpAddr) -> Result<IpNet, CipherError>;
 
-    let destination = Ipv6Addr::new(a, b, c, d, e, f, g, h);
-    destination.to_string()
+// Applies a cipher to an address or range of either family. The key's family
+// decides which rules apply; without an algorithm, IPv4 uses addition and IPv6
+// XOR like the family-specific endpoints.
+fn apply(cipher: Cipher, input: &str, name: &'static str, key: &str, algo: Option<Algorithm>) -> Result<IpNet, IpError> {
+    let (input, key, default) = match key.parse::<IpAddr>() {
+        Ok(IpAddr::V4(key)) => (parse_net_v4(input, name)?, IpAddr::V4(key), Algorithm::Add),
+        Ok(IpAddr::V6(key)) => (parse_net_v6(input, name)?, IpAddr::V6(key), Algorithm::Xor),
+        Err(_) => return Err(IpError::InvalidParameter("key", key.to_string())),
+    };
+    Ok(cipher(algo.unwrap_or(default), input, key)?)
 }
 
+#[derive(Debug, Serialize)]
+struct Transformed {
+    family: &'static str,
+    compact: String,
+    expanded: String,
+}
+
+impl Transformed {
+    fn new(net: IpNet, is_range: bool) -> Self {
+        let suffix = if is_range { format!("/{}", net.prefix_len()) } else { String::new() };
+        Transformed {
+            family: if net.addr().is_ipv4() { "ipv4" } else { "ipv6" },
+            compact: format_net(net, is_range),
+            expanded: expand(net.trunc().addr()) + &suffix,
+        }
+    }
+}
+
+async fn handle_encrypt(params: Query<DestParams>) -> Result<Json<Transformed>, IpError> {
+    // Parse query parameters.
+    let from = required(&params.from, "from")?;
+    let key = required(&params.key, "key")?;
+    let algo = parse_algo(&params.algo)?;
+
+    let destination = apply(ip_cipher::encrypt, from, "from", key, algo)?;
+    Ok(Json(Transformed::new(destination, from.contains('/'))))
+}
 
 #[derive(Debug, Deserialize)]
-struct Keyv6Params {
-    from: Option<Ipv6Addr>,
-    to: Option<Ipv6Addr>,
+struct DecryptParams {
+    to: Option<String>,
+    key: Option<String>,
+    algo: Option<String>,
 }
 
-async fn handle_key_v6(params: Query<Keyv6Params>) -> impl IntoResponse {
+async fn handle_decrypt(params: Query<DecryptParams>) -> Result<Json<Transformed>, IpError> {
     // Parse query parameters.
-    let from = params.from.unwrap_or(Ipv6Addr::new(0,0,0,0,0,0,0,0));
-    let to = params.to.unwrap_or(Ipv6Addr::new(0,0,0,0,0,0,0,0));
-    let from_parts = from.segments().to_vec();
-    let to_parts = to.segments().to_vec();
-
-    let a:u16 = from_parts[0] ^ to_parts[0];
-    let b:u16 = from_parts[1] ^ to_parts[1];
-    let c:u16 = from_parts[2] ^ to_parts[2];
-    let d:u16 = from_parts[3] ^ to_parts[3];
-    let e:u16 = from_parts[4] ^ to_parts[4];
-    let f:u16 = from_parts[5] ^ to_parts[5];
-    let g:u16 = from_parts[6] ^ to_parts[6];
-    let h:u16 = from_parts[7] ^ to_parts[7];
-
-    let destination = Ipv6Addr::new(a, b, c, d, e, f, g, h);
-    destination.to_string()
+    let to = required(&params.to, "to")?;
+    let key = required(&params.key, "key")?;
+    let algo = parse_algo(&params.algo)?;
+
+    let source = apply(ip_cipher::decrypt, to, "to", key, algo)?;
+    Ok(Json(Transformed::new(source, to.contains('/'))))
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchItem {
+    from: String,
+    key: String,
+    algo: Option<Algorithm>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchResult {
+    from: String,
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn batch_dest(item: &BatchItem) -> Result<String, IpError> {
+    let destination = apply(ip_cipher::encrypt, &item.from, "from", &item.key, item.algo)?;
+    Ok(format_net(destination, item.from.contains('/')))
+}
+
+async fn handle_batch(Json(items): Json<Vec<BatchItem>>) -> impl IntoResponse {
+    let results = items.into_iter()
+        .map(|item| {
+            let (dest, error) = match batch_dest(&item) {
+                Ok(dest) => (Some(dest), None),
+                Err(error) => (None, Some(error.to_string())),
+            };
+            BatchResult { from: item.from, key: item.key, dest, error }
+        })
+        .collect::<Vec<BatchResult>>();
+    Json(results)
 }
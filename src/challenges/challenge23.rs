@@ -1,23 +1,29 @@
 // Challenge 23 : https://console.shuttle.dev/shuttlings/cch24/challenge/23
 
 use axum::{
-    response::{IntoResponse},
+    response::{IntoResponse, Json, Response},
     routing::*,
     Router,
-    extract::{Path, Multipart},
-    http::StatusCode,
+    extract::{Path, Multipart, Query, State},
+    http::{StatusCode, header::CONTENT_TYPE},
 };
 use tower_http::services::ServeDir;
 use tera::escape_html;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-pub fn get_routes() -> Router {
+use crate::advisories::{AdvisoryDb, AdvisoryError};
+use crate::lockfile::{self, LockfileError, SourceKind};
+
+pub fn get_routes(advisories: AdvisoryDb) -> Router {
     Router::new()
         .nest_service("/assets/", ServeDir::new("assets"))
         .route("/23/star", get(handle_star))
         .route("/23/present/:color", get(handle_present))
         .route("/23/ornament/:state/:n", get(handle_ornament))
         .route("/23/lockfile", post(handle_lockfile))
+        .route("/23/lockfile/analyze", post(handle_analyze))
+        .route("/23/lockfile/audit", post(handle_audit))
+        .with_state(advisories)
 }
 
 async fn handle_star() -> impl IntoResponse {
@@ -52,32 +58,81 @@ async fn handle_ornament(Path((state, n)): Path<(String, String)>) -> impl IntoR
     };
 }
 
-#[derive(Deserialize, Debug)]
-struct Lockfile {
-    package: Vec<Package>,
+#[derive(Debug)]
+pub enum UploadError {
+    MissingLockfile,
+    InvalidUpload(String),
+    LockfileError(LockfileError),
+    AdvisoryError(AdvisoryError),
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        match self {
+            UploadError::MissingLockfile => {
+                println!("ERR: MissingLockfile");
+                (StatusCode::BAD_REQUEST, "Missing lockfile field".to_string())
+            },
+
+            UploadError::InvalidUpload(rejection) => {
+                println!("ERR: InvalidUpload {}", rejection);
+                (StatusCode::BAD_REQUEST, rejection)
+            },
+
+            UploadError::LockfileError(rejection) => {
+                println!("ERR: {}", rejection);
+                (StatusCode::BAD_REQUEST, rejection.to_string())
+            },
+
+            UploadError::AdvisoryError(rejection) => {
+                println!("ERR: {}", rejection);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Advisory database unavailable".to_string())
+            },
+        }.into_response()
+    }
+}
+
+impl From<LockfileError> for UploadError {
+    fn from(rejection: LockfileError) -> Self {
+        Self::LockfileError(rejection)
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct Package {
-    checksum: Option<String>,
+impl From<AdvisoryError> for UploadError {
+    fn from(rejection: AdvisoryError) -> Self {
+        Self::AdvisoryError(rejection)
+    }
 }
 
-async fn handle_lockfile(mut multipart: Multipart) -> impl IntoResponse {
-    let mut part_data = String::new();
-    while let Ok(Some(part)) = multipart.next_field().await {
+// Contents of the `lockfile` fields of a multipart upload.
+async fn read_lockfile(mut multipart: Multipart) -> Result<String, UploadError> {
+    let mut part_data = None;
+    loop {
+        let part = match multipart.next_field().await {
+            Ok(Some(part)) => part,
+            Ok(None) => break,
+            Err(e) => return Err(UploadError::InvalidUpload(e.body_text())),
+        };
         if part.name() == Some("lockfile") {
-            part_data.push_str(&part.text().await.unwrap());
+            let text = part.text().await.map_err(|e| UploadError::InvalidUpload(e.body_text()))?;
+            part_data.get_or_insert_with(String::new).push_str(&text);
         }
     }
-    let lockfile: Lockfile = match toml::de::from_str(&part_data) {
-        Ok(res) => res,
-        Err(e) => {
-            return (StatusCode::BAD_REQUEST, e.message().to_string()).into_response()
-        },
+    part_data.ok_or(UploadError::MissingLockfile)
+}
+
+async fn handle_lockfile(multipart: Multipart) -> impl IntoResponse {
+    let part_data = match read_lockfile(multipart).await {
+        Ok(part_data) => part_data,
+        Err(e) => return e.into_response(),
+    };
+    let lockfile = match lockfile::parse(&part_data) {
+        Ok(lockfile) => lockfile,
+        Err(e) => return UploadError::from(e).into_response(),
     };
     let mut response = Vec::new();
-    for package in lockfile.package {
-        if let Some(checksum) = package.checksum {
+    for package in &lockfile.package {
+        if let Some(checksum) = lockfile.checksum(package) {
             if checksum.len() < 10 {
                 return (StatusCode::UNPROCESSABLE_ENTITY, "Not enough characters").into_response()
             }
@@ -98,4 +153,81 @@ async fn handle_lockfile(mut multipart: Multipart) -> impl IntoResponse {
         }
     }
     (StatusCode::OK, response.join("\n")).into_response()
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum GraphFormat {
+    #[default]
+    Json,
+    Dot,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnalyzeParams {
+    #[serde(default)]
+    format: GraphFormat,
+}
+
+// Dependency report of a lockfile, or only its graph in Graphviz format.
+async fn handle_analyze(Query(params): Query<AnalyzeParams>, multipart: Multipart) -> Result<Response, UploadError> {
+    let lockfile = lockfile::parse(&read_lockfile(multipart).await?)?;
+    let analysis = lockfile.analyze()?;
+    match params.format {
+        GraphFormat::Json => Ok(Json(analysis).into_response()),
+        GraphFormat::Dot => Ok(([(CONTENT_TYPE, "text/vnd.graphviz")], analysis.to_dot()).into_response()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Finding {
+    name: String,
+    version: String,
+    id: String,
+    title: Option<String>,
+    date: Option<String>,
+    aliases: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    informational: Option<String>,
+    patched: Vec<String>,
+    unaffected: Vec<String>,
+    url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Audit {
+    // Registry packages checked, git and path packages have no advisories.
+    packages: usize,
+    vulnerable: Vec<Finding>,
+    // Informational advisories, like unmaintained or unsound crates.
+    warnings: Vec<Finding>,
+}
+
+// Matches the registry packages of a lockfile against the advisory database.
+async fn handle_audit(State(advisories): State<AdvisoryDb>, multipart: Multipart) -> Result<Json<Audit>, UploadError> {
+    let lockfile = lockfile::parse(&read_lockfile(multipart).await?)?;
+    let index = advisories.index().await?;
+    let mut audit = Audit { packages: 0, vulnerable: Vec::new(), warnings: Vec::new() };
+    for package in lockfile.package.iter().filter(|package| package.source_kind() == SourceKind::Registry) {
+        audit.packages += 1;
+        let version = package.semver()?;
+        for advisory in index.advisories(&package.name) {
+            if advisory.affects(&version) {
+                let findings = if advisory.informational.is_some() { &mut audit.warnings } else { &mut audit.vulnerable };
+                findings.push(Finding {
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                    id: advisory.id.clone(),
+                    title: advisory.title.clone(),
+                    date: advisory.date.clone(),
+                    aliases: advisory.aliases.clone(),
+                    informational: advisory.informational.clone(),
+                    patched: advisory.patched.clone(),
+                    unaffected: advisory.unaffected.clone(),
+                    url: advisory.url.clone(),
+                });
+            }
+        }
+    }
+    Ok(Json(audit))
+}
@@ -1,31 +1,49 @@
 // Challenge 5 : https://console.shuttle.dev/shuttlings/cch24/challenge/5
 
+use std::collections::HashMap;
 use std::fmt;
 use axum::{
-    response::{ IntoResponse, Response},
-    http::{StatusCode, HeaderMap, header::CONTENT_TYPE},
+    response::{ IntoResponse, Json, Response},
+    http::{StatusCode, HeaderMap, HeaderName, HeaderValue, header::{ACCEPT, CONTENT_TYPE}},
     routing::post,
+    extract::{FromRequest, Multipart, Query, Request, State},
     Router
 };
 use cargo_manifest::Manifest;
-use serde::Deserialize;
+use mime::Mime;
+use serde::{Deserialize, Serialize};
 use serde_yaml::Value as YamlValue;
 use serde_json::Value as JsonValue;
 use serde_with::serde_as;
+use sqlx::PgPool;
 
+use crate::formats::{self, Format, FormatError};
+use crate::manifest_lint;
+use crate::manifest_policy::{Policy, Rule, Verdict};
 
+const X_CONVERSION_WARNING: HeaderName = HeaderName::from_static("x-conversion-warning");
+
+
+// Orders live in `package.metadata`, or `workspace.metadata` for a workspace root.
 #[derive(Deserialize)]
 struct Content {
-    package: Package,
+    package: Option<Section>,
+    workspace: Option<Section>,
 }
 
 #[derive(Deserialize)]
-struct Package {
+struct Section {
+    #[serde(default)]
     metadata: Metadata,
-    keywords: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Content {
+    fn metadata(self) -> impl Iterator<Item = Metadata> {
+        [self.package, self.workspace].into_iter().flatten().map(|section| section.metadata)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
 struct Metadata {
     #[serde(default)]
     orders: Vec<Order>,
@@ -49,15 +67,159 @@ impl fmt::Display for Order {
     }
 }
 
-const MAGIC_KEYWORD: &str = "Christmas 2024";
+#[derive(Debug, Serialize)]
+struct OrderLine {
+    item: String,
+    quantity: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct SkippedOrder {
+    item: String,
+    reason: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct Orders {
+    orders: Vec<OrderLine>,
+    skipped: Vec<SkippedOrder>,
+    policy: Vec<Verdict>,
+}
+
+// Why an order was left out. `DefaultOnError` turns any bad quantity into
+// `None`, so the reason comes from the raw order.
+fn skip_reason(order: Option<&toml::Value>) -> &'static str {
+    match order.and_then(|order| order.get("quantity")) {
+        None => "missing quantity",
+        Some(toml::Value::Integer(_)) => "quantity out of range",
+        Some(_) => "quantity is not an integer",
+    }
+}
+
+// Orders as written in the manifest, in the same order as `Content::metadata`.
+fn raw_orders(payload: &str) -> Vec<toml::Value> {
+    let Ok(table) = toml::from_str::<toml::Table>(payload) else {
+        return Vec::new();
+    };
+    ["package", "workspace"].iter()
+        .filter_map(|section| table.get(*section)?.get("metadata")?.get("orders")?.as_array().cloned())
+        .flatten()
+        .collect()
+}
+
+// Unit prices by item from `metadata.prices`, taking precedence over the price
+// list in the database. Only the summary uses them, and entries that are not
+// numbers are ignored.
+fn read_prices(payload: &str) -> HashMap<String, f64> {
+    let Ok(table) = toml::from_str::<toml::Table>(payload) else {
+        return HashMap::new();
+    };
+    ["package", "workspace"].iter()
+        .filter_map(|section| table.get(*section)?.get("metadata")?.get("prices")?.as_table().cloned())
+        .flatten()
+        .filter_map(|(item, price)| match price {
+            toml::Value::Integer(price) => Some((item, price as f64)),
+            toml::Value::Float(price) => Some((item, price)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn accepts_json(headers: &HeaderMap) -> bool {
+    headers.get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.split(',')
+            .filter_map(|media| media.trim().parse::<Mime>().ok())
+            .any(|media| media.essence_str() == mime::APPLICATION_JSON.essence_str()))
+        .unwrap_or(false)
+}
+
+#[derive(Debug, Serialize)]
+struct ItemSummary {
+    item: String,
+    quantity: u64,
+    unit_price: Option<f64>,
+    total: Option<f64>,
+}
+
+// Totals only cover priced items, the others are listed as unpriced.
+#[derive(Debug, Serialize)]
+struct OrderSummary {
+    items: Vec<ItemSummary>,
+    quantity: u64,
+    total: f64,
+    unpriced: Vec<String>,
+    policy: Vec<Verdict>,
+}
+
+#[derive(Debug, Serialize)]
+struct ValidationReport {
+    valid: bool,
+    problems: Vec<manifest_lint::Problem>,
+}
+
+// Syntax error of a manifest, with its position when the parser knows it.
+#[derive(Debug, Serialize)]
+pub struct ParseProblem {
+    message: String,
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+impl ParseProblem {
+    // Parsers append the position to their messages, it is reported separately.
+    fn new(message: String, line: Option<usize>, column: Option<usize>) -> Self {
+        let message = match (line, column) {
+            (Some(line), Some(column)) => message.replacen(&format!(" at line {line} column {column}"), "", 1),
+            _ => message,
+        };
+        ParseProblem { message, line, column }
+    }
+
+    fn from_toml(rejection: toml::de::Error, source: &str) -> Self {
+        let position = rejection.span().map(|span| manifest_lint::position(source, span.start));
+        ParseProblem::new(rejection.message().trim().to_string(), position.map(|(line, _)| line), position.map(|(_, column)| column))
+    }
+
+    fn into_response(self, error: &str) -> Response {
+        println!("ERR: {} {}", error, self.message);
+        let body = serde_json::json!({"error": error, "message": self.message, "line": self.line, "column": self.column});
+        (StatusCode::BAD_REQUEST, Json(body)).into_response()
+    }
+}
+
+impl From<serde_json::Error> for ParseProblem {
+    fn from(rejection: serde_json::Error) -> Self {
+        // serde_json reports column 0 at the very end of the input.
+        let column = Some(rejection.column()).filter(|&column| column > 0);
+        ParseProblem::new(rejection.to_string(), Some(rejection.line()), column)
+    }
+}
+
+impl From<serde_yaml::Error> for ParseProblem {
+    fn from(rejection: serde_yaml::Error) -> Self {
+        let location = rejection.location();
+        ParseProblem::new(rejection.to_string(), location.as_ref().map(|l| l.line()), location.as_ref().map(|l| l.column()))
+    }
+}
 
 //
 pub enum ManifestError {
     NoContent,
     TomlParserError(toml::de::Error),
     CargoManifestError(cargo_manifest::Error),
-    MagicKeywordNotFound,
-    ContentTypeNotSupported,
+    // Verdicts of every rule, as JSON when the client asked for it.
+    PolicyViolation(Vec<Verdict>, bool),
+    TomlSyntaxError(ParseProblem),
+    YamlSyntaxError(ParseProblem),
+    JsonSyntaxError(ParseProblem),
+    // No media type, and the content is not recognisably TOML, YAML or JSON.
+    UnknownFormat,
+    InvalidContentType(String),
+    UnsupportedMediaType(String),
+    UnsupportedCharset(String),
+    DatabaseError(sqlx::Error),
+    ConversionError(FormatError),
 }
 
 impl IntoResponse for ManifestError {
@@ -80,16 +242,58 @@ impl IntoResponse for ManifestError {
                 (StatusCode::BAD_REQUEST, INVALID_MANIFEST_DETAIL)
             },
 
-            ManifestError::MagicKeywordNotFound => {
-                println!("ERR: MagicKeywordNotFound");
-                (StatusCode::BAD_REQUEST, "Magic keyword not provided")
+            ManifestError::PolicyViolation(verdicts, json) => {
+                println!("ERR: PolicyViolation");
+                if json {
+                    let body = serde_json::json!({"error": "Manifest policy violated", "policy": verdicts});
+                    return (StatusCode::BAD_REQUEST, Json(body)).into_response();
+                }
+                // A missing keyword keeps the challenge's message, other rules explain themselves.
+                let failed = verdicts.iter().filter(|verdict| !verdict.passed).collect::<Vec<&Verdict>>();
+                match failed.first() {
+                    Some(verdict) if failed.iter().all(|verdict| verdict.rule != Rule::RequiredKeywords) => {
+                        return (StatusCode::BAD_REQUEST, verdict.detail.clone()).into_response();
+                    },
+                    _ => (StatusCode::BAD_REQUEST, "Magic keyword not provided"),
+                }
+            },
+
+            ManifestError::TomlSyntaxError(problem) => return problem.into_response("Invalid TOML"),
+
+            ManifestError::YamlSyntaxError(problem) => return problem.into_response("Invalid YAML"),
+
+            ManifestError::JsonSyntaxError(problem) => return problem.into_response("Invalid JSON"),
+
+            ManifestError::UnknownFormat => {
+                println!("ERR: UnknownFormat");
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unable to detect the manifest format")
+            },
+
+            ManifestError::InvalidContentType(content_type) => {
+                println!("ERR: InvalidContentType {}", content_type);
+                (StatusCode::BAD_REQUEST, "Invalid Content-Type")
             },
 
-            ManifestError::ContentTypeNotSupported => {
-                println!("ERR: ContentTypeNotSupported");
+            ManifestError::UnsupportedMediaType(media_type) => {
+                println!("ERR: UnsupportedMediaType {}", media_type);
                 (StatusCode::UNSUPPORTED_MEDIA_TYPE, "")
             },
 
+            ManifestError::UnsupportedCharset(charset) => {
+                println!("ERR: UnsupportedCharset {}", charset);
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Only UTF-8 is supported")
+            },
+
+            ManifestError::DatabaseError(rejection) => {
+                println!("{}", rejection);
+                (StatusCode::INTERNAL_SERVER_ERROR, "")
+            },
+
+            ManifestError::ConversionError(rejection) => {
+                println!("{}", rejection);
+                return (StatusCode::BAD_REQUEST, rejection.to_string()).into_response();
+            },
+
         }.into_response()
     }
 }
@@ -106,63 +310,192 @@ impl From<cargo_manifest::Error> for ManifestError {
     }
 }
 
-pub fn get_routes() -> Router {
+impl From<sqlx::Error> for ManifestError {
+    fn from(rejection: sqlx::Error) -> Self {
+        Self::DatabaseError(rejection)
+    }
+}
+
+impl From<FormatError> for ManifestError {
+    fn from(rejection: FormatError) -> Self {
+        Self::ConversionError(rejection)
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState {
+    pool: PgPool,
+    policy: Policy,
+}
+
+pub fn get_routes(pool: PgPool, policy: Policy) -> Router {
+    let state = AppState { pool, policy };
     Router::new()
         .route("/5/manifest", post(handle_manifest))
+        .route("/5/manifest/summary", post(handle_summary))
+        .route("/5/validate", post(handle_validate))
+        .route("/5/convert", post(handle_convert))
+        .with_state(state)
+}
 
+pub fn convert_json_to_toml(json: &str) -> Result<String, ManifestError> {
+    let value: JsonValue = serde_json::from_str(json)
+        .map_err(|e| ManifestError::JsonSyntaxError(e.into()))?;
+    if !value.is_object() {
+        return Err(FormatError::NotATable.into());
+    }
+    serialize_toml(&value)
 }
 
-pub fn convert_json_to_toml(json: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let value: JsonValue = serde_json::from_str(json)?;
-    let toml = toml::to_string(&value)?;
-    Ok(toml)
+pub fn convert_yaml_to_toml(yaml: &str) -> Result<String, ManifestError> {
+    let value: YamlValue = serde_yaml::from_str(yaml)
+        .map_err(|e| ManifestError::YamlSyntaxError(e.into()))?;
+    if !value.is_mapping() {
+        return Err(FormatError::NotATable.into());
+    }
+    serialize_toml(&value)
 }
 
-pub fn convert_yaml_to_toml(yaml: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let value: YamlValue = serde_yaml::from_str(yaml)?;
-    let toml = toml::to_string(&value)?;
-    Ok(toml)
+// Well-formed documents may still have no TOML equivalent, like a null.
+fn serialize_toml<T: Serialize>(value: &T) -> Result<String, ManifestError> {
+    toml::to_string(value).map_err(|e| FormatError::Serialize(Format::Toml, e.to_string()).into())
 }
 
-async fn handle_manifest(headers: HeaderMap, body: String) ->  Result<String, ManifestError> {
-    let content_type = headers.get(CONTENT_TYPE).unwrap().to_str().unwrap();
-    //println!("Content Type: {:?}", content_type);
-    let content: Content;
+fn to_toml(format: Format, body: String) -> Result<String, ManifestError> {
+    match format {
+        Format::Toml => {
+            toml::from_str::<toml::Table>(&body)
+                .map_err(|e| ManifestError::TomlSyntaxError(ParseProblem::from_toml(e, &body)))?;
+            Ok(body)
+        },
+        Format::Yaml => convert_yaml_to_toml(&body),
+        Format::Json => convert_json_to_toml(&body),
+    }
+}
 
-    let payload  = match content_type {
-        // TOML
-        "application/toml" => {
-            body
+// Format of a document from its media type, parameters included, or from its
+// content when it has none.
+fn media_format(content_type: Option<&str>, body: &str) -> Result<Format, ManifestError> {
+    let Some(content_type) = content_type else {
+        return Format::sniff(body).ok_or(ManifestError::UnknownFormat);
+    };
+    let mime = content_type.parse::<Mime>()
+        .map_err(|_| ManifestError::InvalidContentType(content_type.to_string()))?;
+    if let Some(charset) = mime.get_param(mime::CHARSET) {
+        if charset != mime::UTF_8 {
+            return Err(ManifestError::UnsupportedCharset(charset.to_string()));
         }
-        // YAML
-        "application/yaml"  => match convert_yaml_to_toml(&body) {
-            Err(_) => return Err(ManifestError::ContentTypeNotSupported),
-            Ok(toml) => toml,
-        },
-        // JSON
-        "application/json" => match convert_json_to_toml(&body) {
-            Err(_) => return Err(ManifestError::ContentTypeNotSupported),
-            Ok(toml) => toml,
+    }
+    Format::from_content_type(mime.essence_str())
+        .ok_or_else(|| ManifestError::UnsupportedMediaType(mime.essence_str().to_string()))
+}
+
+fn request_format(headers: &HeaderMap, body: &str) -> Result<Format, ManifestError> {
+    let content_type = headers.get(CONTENT_TYPE)
+        .map(|v| v.to_str().map_err(|_| ManifestError::InvalidContentType(String::from_utf8_lossy(v.as_bytes()).to_string())))
+        .transpose()?;
+    media_format(content_type, body)
+}
+
+// File uploads often come as application/octet-stream, so fall back on the
+// extension, then on the content.
+fn part_format(content_type: Option<&str>, file_name: Option<&str>, body: &str) -> Result<Format, ManifestError> {
+    let generic = content_type
+        .and_then(|content_type| content_type.parse::<Mime>().ok())
+        .is_some_and(|mime| mime.essence_str() == mime::APPLICATION_OCTET_STREAM.essence_str()
+            || mime.essence_str() == mime::TEXT_PLAIN.essence_str());
+    match content_type {
+        Some(content_type) if !generic => media_format(Some(content_type), body),
+        _ => match file_name.and_then(|name| name.rsplit_once('.')).map(|(_, extension)| extension) {
+            Some("toml") => Ok(Format::Toml),
+            Some("yaml") | Some("yml") => Ok(Format::Yaml),
+            Some("json") => Ok(Format::Json),
+            _ => media_format(None, body),
         },
-        _ => return Err(ManifestError::ContentTypeNotSupported)
-    };
+    }
+}
+
+// Converts the manifests of a request to TOML: a single manifest, or a workspace
+// root and its members as a multipart upload. Each must be a valid manifest with
+// passes the policy, with fields inherited from the workspace root.
+async fn read_manifests(request: Request, policy: &Policy, json: bool) -> Result<(Vec<String>, Vec<Verdict>), ManifestError> {
+    let headers = request.headers().clone();
+    //println!("Content Type: {:?}", headers.get(CONTENT_TYPE));
 
-    //println!("Payload:\n{:?}", payload);
-    let manifest = Manifest::from_slice(&payload.as_bytes())?; //toml::de::Error
-    if let Some(keywords) = manifest.package.unwrap().keywords {
-        let keywords = keywords.as_local().unwrap();
-        if !keywords.contains(&String::from(MAGIC_KEYWORD)) {
-            return Err(ManifestError::MagicKeywordNotFound);
+    let mut payloads = Vec::new();
+    let multipart = headers.get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<Mime>().ok())
+        .is_some_and(|mime| mime.essence_str() == mime::MULTIPART_FORM_DATA.essence_str());
+    if multipart {
+        let mut multipart = Multipart::from_request(request, &()).await
+            .map_err(|e| ManifestError::InvalidContentType(e.body_text()))?;
+        // A malformed upload is rejected, rather than cut short at the bad part.
+        loop {
+            let part = match multipart.next_field().await {
+                Ok(Some(part)) => part,
+                Ok(None) => break,
+                Err(e) => return Err(ManifestError::InvalidContentType(e.body_text())),
+            };
+            let content_type = part.content_type().map(str::to_string);
+            let file_name = part.file_name().map(str::to_string);
+            let body = part.text().await.map_err(|e| ManifestError::InvalidContentType(e.body_text()))?;
+            let format = part_format(content_type.as_deref(), file_name.as_deref(), &body)?;
+            payloads.push(to_toml(format, body)?);
         }
     } else {
-        return Err(ManifestError::MagicKeywordNotFound);
+        let body = String::from_request(request, &()).await.map_err(|_| ManifestError::NoContent)?;
+        payloads.push(to_toml(request_format(&headers, &body)?, body)?);
+    }
+
+    //println!("Payload:\n{:?}", payloads);
+    let manifests = payloads.iter()
+        .map(|payload| Manifest::from_slice(payload.as_bytes())) //toml::de::Error
+        .collect::<Result<Vec<Manifest>, cargo_manifest::Error>>()?;
+    let root = manifests.iter().find(|manifest| manifest.workspace.is_some());
+    let verdicts = manifests.iter()
+        .flat_map(|manifest| policy.evaluate(manifest, root))
+        .collect::<Vec<Verdict>>();
+    if verdicts.iter().any(|verdict| !verdict.passed) {
+        return Err(ManifestError::PolicyViolation(verdicts, json));
+    }
+
+    Ok((payloads, verdicts))
+}
+
+// Orders of every manifest, in upload order.
+fn read_orders(payloads: &[String]) -> Result<Vec<(Order, Option<toml::Value>)>, ManifestError> {
+    let mut orders = Vec::new();
+    for payload in payloads {
+        let content = toml::from_str::<Content>(payload)?;
+        //println!("Content:\n{:?}", content);
+        let mut raw = raw_orders(payload).into_iter();
+        for order in content.metadata().flat_map(|metadata| metadata.orders) {
+            orders.push((order, raw.next()));
+        }
     }
+    Ok(orders)
+}
+
+async fn handle_manifest(State(state): State<AppState>, request: Request) ->  Result<Response, ManifestError> {
+    let json = accepts_json(request.headers());
+    let (payloads, policy) = read_manifests(request, &state.policy, json).await?;
+    let orders = read_orders(&payloads)?;
 
-    content = toml::from_str::<Content>(&payload)?;
-    //println!("Content:\n{:?}", content);
+    if json {
+        let mut response = Orders { orders: Vec::new(), skipped: Vec::new(), policy };
+        for (order, raw) in orders {
+            match order.quantity {
+                Some(quantity) => response.orders.push(OrderLine { item: order.item, quantity }),
+                None => response.skipped.push(SkippedOrder { item: order.item, reason: skip_reason(raw.as_ref()) }),
+            }
+        }
+        return Ok(Json(response).into_response());
+    }
 
-    let order_items = content.package.metadata.orders
+    let order_items = orders
         .into_iter()
+        .map(|(order, _)| order)
         .filter(|order| order.quantity.is_some())
         .map(|order| order.to_string())
         .collect::<Vec<String>>();
@@ -172,5 +505,96 @@ async fn handle_manifest(headers: HeaderMap, body: String) ->  Result<String, Ma
         return Err(ManifestError::NoContent);
     }
 
-    Ok(order_items.join("\n"))
+    Ok(order_items.join("\n").into_response())
+}
+
+async fn handle_summary(State(state): State<AppState>, request: Request) -> Result<Json<OrderSummary>, ManifestError> {
+    let json = accepts_json(request.headers());
+    let (payloads, policy) = read_manifests(request, &state.policy, json).await?;
+    let mut orders = Vec::new();
+    let mut manifest_prices = HashMap::new();
+    for payload in &payloads {
+        for metadata in toml::from_str::<Content>(payload)?.metadata() {
+            orders.extend(metadata.orders);
+        }
+        manifest_prices.extend(read_prices(payload));
+    }
+
+    // Merge duplicate items, keeping the order they first appear in.
+    let mut items: Vec<(String, u64)> = Vec::new();
+    for order in orders {
+        let Some(quantity) = order.quantity else {
+            continue;
+        };
+        match items.iter_mut().find(|(item, _)| *item == order.item) {
+            Some((_, total)) => *total += quantity as u64,
+            None => items.push((order.item, quantity as u64)),
+        }
+    }
+    if items.is_empty() {
+        return Err(ManifestError::NoContent);
+    }
+
+    let names = items.iter().map(|(item, _)| item.clone()).collect::<Vec<String>>();
+    let mut prices = sqlx::query_as::<_, (String, f64)>("SELECT item, price FROM prices WHERE item = ANY($1)")
+        .bind(&names)
+        .fetch_all(&state.pool)
+        .await?
+        .into_iter()
+        .collect::<HashMap<String, f64>>();
+    prices.extend(manifest_prices);
+
+    let mut summary = OrderSummary { items: Vec::new(), quantity: 0, total: 0.0, unpriced: Vec::new(), policy };
+    for (item, quantity) in items {
+        let unit_price = prices.get(&item).copied();
+        let total = unit_price.map(|price| price * quantity as f64);
+        summary.quantity += quantity;
+        match total {
+            Some(total) => summary.total += total,
+            None => summary.unpriced.push(item.clone()),
+        }
+        summary.items.push(ItemSummary { item, quantity, unit_price, total });
+    }
+    Ok(Json(summary))
+}
+
+// Positions are reported against the original TOML, so only TOML is accepted.
+async fn handle_validate(headers: HeaderMap, body: String) -> Result<Json<ValidationReport>, ManifestError> {
+    if request_format(&headers, &body)? != Format::Toml {
+        return Err(ManifestError::UnsupportedMediaType(headers.get(CONTENT_TYPE)
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).to_string())
+            .unwrap_or_default()));
+    }
+    let problems = manifest_lint::lint(&body);
+    Ok(Json(ValidationReport { valid: problems.is_empty(), problems }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ConvertParams {
+    to: Format,
+}
+
+// Keeps printable ASCII but `%`, everything else is percent-encoded as UTF-8.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b' '..=b'~' if b != b'%' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+// Lossy conversions are reported as `X-Conversion-Warning` headers, one per
+// value, percent-encoded since keys may hold any character.
+async fn handle_convert(Query(params): Query<ConvertParams>, headers: HeaderMap, body: String) -> Result<Response, ManifestError> {
+    let from = request_format(&headers, &body)?;
+    let conversion = formats::convert(&body, from, params.to)?;
+
+    let mut response = ([(CONTENT_TYPE, params.to.content_type())], conversion.output).into_response();
+    for warning in conversion.warnings {
+        let value = HeaderValue::from_str(&percent_encode(&warning))
+            .expect("percent-encoded text is a valid header value");
+        response.headers_mut().append(X_CONVERSION_WARNING, value);
+    }
+    Ok(response)
 }
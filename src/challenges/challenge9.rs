@@ -1,52 +1,143 @@
 // Challenge 9 : https://console.shuttle.dev/shuttlings/cch24/challenge/9
 
-use std::{sync::{Arc, RwLock}};
+use std::time::Duration;
 
 use axum::{
     Router,
-    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
-    response::IntoResponse,
-    routing::post,
-    extract::{State}
+    http::{header::{AUTHORIZATION, CONTENT_TYPE}, HeaderMap, StatusCode},
+    response::{IntoResponse, Json},
+    routing::{get, post},
+    extract::{Query, Request, State}
     
 };
-use leaky_bucket::RateLimiter;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sqlx::{FromRow, PgPool};
+
+use crate::rate_limit::RateLimitLayer;
+use crate::units;
 
 #[derive(Clone)]
 pub struct AppState {
-    limiter: Arc<RwLock<RateLimiter>>,
+    pool: PgPool,
+    limit: RateLimitLayer,
+    // Token of the bucket admin endpoints, which are disabled without one.
+    admin_token: Option<String>,
 }
 
-pub fn get_routes() -> Router {
+pub fn get_routes(pool: PgPool, limit: RateLimitLayer, admin_token: Option<String>) -> Router {
 
-    let limiter = RateLimiter::builder()
-        .interval(std::time::Duration::from_secs(1))
-        .max(5)
-        .initial(5)
-        .build();
-    let limiter = Arc::new(RwLock::new(limiter));
-    let state = AppState{ limiter };
+    let state = AppState{ pool, limit: limit.clone(), admin_token };
     
     Router::new()
-        .route("/9/milk", post(handle_milk))
+        .route("/9/milk", post(handle_milk).layer(limit))
         .route("/9/refill", post(handle_refill))
+        .route("/9/convert", post(handle_convert))
+        .route("/9/bucket", get(handle_bucket).put(handle_bucket_update))
+        .route("/9/ledger", get(handle_ledger))
         .with_state(state)
 }
 
 #[derive(serde::Deserialize, Debug)]
 struct Volume {
-    gallons: Option<f32>,
-    liters: Option<f32>,
-    litres: Option<f32>,
-    pints: Option<f32>,
+    gallons: Option<f64>,
+    liters: Option<f64>,
+    litres: Option<f64>,
+    pints: Option<f64>,
+}
+
+// Number of decimals to round converted values to.
+#[derive(Debug, Deserialize)]
+struct Rounding {
+    precision: Option<u32>,
+}
+
+impl Rounding {
+    fn apply(&self, value: f64) -> f64 {
+        match self.precision {
+            Some(precision) => units::round(value, precision),
+            None => value,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.precision.unwrap_or(0) <= units::MAX_PRECISION
+    }
+}
+
+// Volume of milk taken from or added to the stock, in liters by default.
+#[derive(Debug, Deserialize)]
+struct Amount {
+    volume: Option<f64>,
+    unit: Option<String>,
+}
+
+impl Amount {
+    fn liters(&self) -> Option<Result<f64, units::ConversionError>> {
+        let volume = self.volume?;
+        Some(units::convert(volume, self.unit.as_deref().unwrap_or("liter"), "liter"))
+    }
+}
+
+#[derive(Debug, FromRow, Serialize)]
+struct LedgerEntry {
+    id: i64,
+    operation: String,
+    liters: f64,
+    balance: f64,
+    created_at: chrono::DateTime<chrono::Utc>,
 }
 
-async fn handle_milk(State(state): State<AppState>, headers: HeaderMap, body: String) ->  impl IntoResponse {
-    if !state.limiter.read().unwrap().try_acquire(1) {
-        return (StatusCode::TOO_MANY_REQUESTS, "No milk available\n").into_response()
+// Applies a stock change and records it in the ledger. Withdrawals fail
+// with `None` when the stock is too low.
+async fn record(pool: &PgPool, operation: &str, liters: f64) -> Result<Option<LedgerEntry>, sqlx::Error> {
+    let change = if operation == "withdrawal" { -liters } else { liters };
+    let mut tx = pool.begin().await?;
+    let Some(balance) = sqlx::query_scalar::<_, f64>("UPDATE milk_stock SET liters = liters + $1 \
+                                WHERE id = 1 AND liters + $1 >= 0 RETURNING liters")
+        .bind(change)
+        .fetch_optional(&mut *tx)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let entry = sqlx::query_as::<_, LedgerEntry>("INSERT INTO milk_ledger (operation, liters, balance) \
+                                VALUES ($1, $2, $3) RETURNING *")
+        .bind(operation)
+        .bind(liters)
+        .bind(balance)
+        .fetch_one(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some(entry))
+}
+
+async fn handle_milk(
+    State(state): State<AppState>,
+    Query(amount): Query<Amount>,
+    Query(rounding): Query<Rounding>,
+    headers: HeaderMap,
+    body: String,
+) ->  impl IntoResponse {
+    // Withdraw a given volume from the stock.
+    if let Some(liters) = amount.liters() {
+        let liters = match liters {
+            Ok(liters) => liters,
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+        return match record(&state.pool, "withdrawal", liters).await {
+            Ok(Some(entry)) => (StatusCode::OK, Json(entry)).into_response(),
+            Ok(None) => (StatusCode::CONFLICT, "Not enough milk in stock\n").into_response(),
+            Err(e) => {
+                println!("{}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        };
     }
     if let Some("application/json") = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+        if !rounding.is_valid() {
+            return (StatusCode::BAD_REQUEST, "Invalid precision").into_response()
+        }
         let volume = serde_json::from_str::<Volume>(&body)
             .map_err(|_e| StatusCode::BAD_REQUEST.into_response());
         if volume.is_err() {
@@ -54,42 +145,156 @@ async fn handle_milk(State(state): State<AppState>, headers: HeaderMap, body: St
         }
         //println!("Volume: {:?}", volume);
         let volume = volume.unwrap();
-        match (volume.gallons, volume.liters, volume.litres, volume.pints) {
+        // Value, its unit, the unit to convert to and the response field.
+        let (value, from, to, field) = match (volume.gallons, volume.liters, volume.litres, volume.pints) {
             // Gallons 
-            (Some(gallons), None, None, None) => {
-                let liters = gallons * 3.78541;
-                (StatusCode::OK, json!({"liters": liters}).to_string()).into_response()
-            },
+            (Some(gallons), None, None, None) => (gallons, "gallon", "liter", "liters"),
             // Liters
-            (None, Some(liters), None, None) => {
-                let gallons = liters / 3.78541;
-                (StatusCode::OK, json!({"gallons": gallons}).to_string()).into_response()
-            }
+            (None, Some(liters), None, None) => (liters, "liter", "gallon", "gallons"),
             // UK Litres
-            (None, None, Some(litres), None) => {
-                let pints = litres * 1.759754;
-                (StatusCode::OK, json!({"pints": pints}).to_string()).into_response()
-            }
+            (None, None, Some(litres), None) => (litres, "litre", "pint", "pints"),
             // UK Pints
-            (None, None, None, Some(pints)) => {
-                let litres = pints / 1.759754;
-                (StatusCode::OK, json!({"litres": litres}).to_string()).into_response()
-            }
-            _ => StatusCode::BAD_REQUEST.into_response()
+            (None, None, None, Some(pints)) => (pints, "pint", "litre", "litres"),
+            _ => return StatusCode::BAD_REQUEST.into_response()
+        };
+        match units::convert(value, from, to) {
+            Ok(converted) => (StatusCode::OK, json!({field: rounding.apply(converted)}).to_string()).into_response(),
+            Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
         }
     } else {
         (StatusCode::OK, "Milk withdrawn\n").into_response()
     }
 }
 
-async fn handle_refill(State(state): State<AppState>) ->  impl IntoResponse {
-    // Create a new bucket.
-    let limiter = RateLimiter::builder()
-        .interval(std::time::Duration::from_secs(1))
-        .max(5)
-        .initial(5)
-        .build();
-    // Update state. 
-    *state.limiter.write().unwrap() = limiter;
+#[derive(Debug, Deserialize)]
+struct Conversion {
+    value: f64,
+    from: String,
+    to: String,
+}
+
+async fn handle_convert(Query(rounding): Query<Rounding>, Json(conversion): Json<Conversion>) -> impl IntoResponse {
+    if !rounding.is_valid() {
+        return (StatusCode::BAD_REQUEST, "Invalid precision").into_response()
+    }
+    match units::convert(conversion.value, &conversion.from, &conversion.to) {
+        Ok(value) => (StatusCode::OK, Json(json!({"value": rounding.apply(value), "unit": conversion.to}))).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+// Rate limit buckets are only refilled with the milk, not on invalid requests.
+async fn handle_refill(State(state): State<AppState>, Query(amount): Query<Amount>) ->  impl IntoResponse {
+    // Add a given volume to the stock.
+    if let Some(liters) = amount.liters() {
+        let liters = match liters {
+            Ok(liters) => liters,
+            Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+        return match record(&state.pool, "refill", liters).await {
+            Ok(Some(entry)) => {
+                state.limit.reset().await;
+                (StatusCode::OK, Json(entry)).into_response()
+            },
+            // Refills never go negative, so the stock row is missing.
+            Ok(None) => {
+                println!("ERR: No milk stock to refill");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+            Err(e) => {
+                println!("{}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        };
+    }
+    state.limit.reset().await;
     StatusCode::OK.into_response()
 }
+
+// Inclusive range of days.
+#[derive(Debug, Deserialize)]
+struct LedgerRange {
+    from: Option<chrono::NaiveDate>,
+    to: Option<chrono::NaiveDate>,
+}
+
+#[derive(Debug, FromRow, Serialize)]
+struct DailyTotal {
+    day: chrono::NaiveDate,
+    withdrawn: f64,
+    refilled: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct Ledger {
+    entries: Vec<LedgerEntry>,
+    days: Vec<DailyTotal>,
+}
+
+async fn handle_ledger(State(state): State<AppState>, Query(range): Query<LedgerRange>) -> Result<Json<Ledger>, StatusCode> {
+    const RANGE: &str = "($1::date IS NULL OR created_at::date >= $1) AND ($2::date IS NULL OR created_at::date <= $2)";
+    let entries = sqlx::query_as::<_, LedgerEntry>(&format!("SELECT * FROM milk_ledger WHERE {RANGE} ORDER BY created_at, id"))
+        .bind(range.from)
+        .bind(range.to)
+        .fetch_all(&state.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let days = sqlx::query_as::<_, DailyTotal>(&format!("SELECT created_at::date AS day, \
+                                COALESCE(SUM(liters) FILTER (WHERE operation = 'withdrawal'), 0) AS withdrawn, \
+                                COALESCE(SUM(liters) FILTER (WHERE operation = 'refill'), 0) AS refilled \
+                                FROM milk_ledger WHERE {RANGE} GROUP BY day ORDER BY day"))
+        .bind(range.from)
+        .bind(range.to)
+        .fetch_all(&state.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(Ledger { entries, days }))
+}
+
+// Admin requests carry `Authorization: Bearer <token>`.
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(expected) = &state.admin_token else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let token = headers.get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    // Digests are compared so the time taken tells nothing about the token.
+    let digest = |value: &str| ring::digest::digest(&ring::digest::SHA256, value.as_bytes());
+    if digest(token).as_ref() != digest(expected).as_ref() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+async fn handle_bucket(State(state): State<AppState>, request: Request) -> impl IntoResponse {
+    if let Err(status) = authorize(&state, request.headers()) {
+        return status.into_response();
+    }
+    Json(state.limit.status(request).await).into_response()
+}
+
+// Omitted fields keep their current value.
+#[derive(Debug, Deserialize)]
+struct BucketUpdate {
+    max: Option<usize>,
+    refill: Option<usize>,
+    interval_ms: Option<u64>,
+}
+
+async fn handle_bucket_update(State(state): State<AppState>, headers: HeaderMap, Json(update): Json<BucketUpdate>) -> impl IntoResponse {
+    if let Err(status) = authorize(&state, &headers) {
+        return status.into_response();
+    }
+    if [update.max, update.refill].contains(&Some(0)) || update.interval_ms == Some(0) {
+        return (StatusCode::BAD_REQUEST, "Bucket parameters must be positive").into_response()
+    }
+    match state.limit.reconfigure(update.max, update.refill, update.interval_ms.map(Duration::from_millis)).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            println!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        },
+    }
+}
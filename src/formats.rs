@@ -0,0 +1,258 @@
+// Conversion between TOML, YAML and JSON documents. Values go through a JSON
+// tree, which keeps key order and notes everything the target cannot hold.
+
+use std::fmt;
+
+use serde::Deserialize;
+use serde_json::{Map, Number, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    pub fn from_content_type(content_type: &str) -> Option<Format> {
+        match content_type {
+            "application/toml" => Some(Format::Toml),
+            "application/yaml" | "application/x-yaml" | "text/yaml" => Some(Format::Yaml),
+            "application/json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    // Guesses the format of a document without a media type. JSON and TOML are
+    // strict enough to tell apart, YAML accepts almost anything so it comes last.
+    pub fn sniff(input: &str) -> Option<Format> {
+        if serde_json::from_str::<Value>(input).is_ok_and(|value| value.is_object()) {
+            return Some(Format::Json);
+        }
+        if toml::from_str::<toml::Table>(input).is_ok() {
+            return Some(Format::Toml);
+        }
+        if serde_yaml::from_str::<serde_yaml::Value>(input).is_ok_and(|value| value.is_mapping()) {
+            return Some(Format::Yaml);
+        }
+        None
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Toml => "TOML",
+            Format::Yaml => "YAML",
+            Format::Json => "JSON",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Toml => "application/toml",
+            Format::Yaml => "application/yaml",
+            Format::Json => "application/json",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    Parse(Format, String),
+    Serialize(Format, String),
+    // TOML documents are tables.
+    NotATable,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Parse(format, e) => write!(f, "Invalid {}: {e}", format.name()),
+            FormatError::Serialize(format, e) => write!(f, "Cannot write {}: {e}", format.name()),
+            FormatError::NotATable => write!(f, "A TOML document must be a table"),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+// A converted document with the parts that did not convert faithfully.
+pub struct Conversion {
+    pub output: String,
+    pub warnings: Vec<String>,
+}
+
+struct Converter {
+    warnings: Vec<String>,
+}
+
+impl Converter {
+    fn warn(&mut self, path: &str, message: &str) {
+        let path = if path.is_empty() { "document" } else { path };
+        self.warnings.push(format!("{path}: {message}"));
+    }
+
+    fn yaml_value(&mut self, value: serde_yaml::Value, path: &str) -> Value {
+        match value {
+            serde_yaml::Value::Null => Value::Null,
+            serde_yaml::Value::Bool(b) => Value::Bool(b),
+            serde_yaml::Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    Value::from(i)
+                } else if let Some(u) = n.as_u64() {
+                    Value::from(u)
+                } else {
+                    let f = n.as_f64().unwrap_or(f64::NAN);
+                    Number::from_f64(f).map(Value::Number).unwrap_or_else(|| {
+                        self.warn(path, &format!("{f} is not a finite number, replaced with null"));
+                        Value::Null
+                    })
+                }
+            },
+            serde_yaml::Value::String(s) => Value::String(s),
+            serde_yaml::Value::Sequence(items) => Value::Array(items.into_iter()
+                .enumerate()
+                .map(|(i, item)| self.yaml_value(item, &format!("{path}[{i}]")))
+                .collect()),
+            serde_yaml::Value::Mapping(mapping) => {
+                let mut map = Map::new();
+                for (key, value) in mapping {
+                    let key = match key {
+                        serde_yaml::Value::String(key) => key,
+                        other => {
+                            let key = serde_yaml::to_string(&other).unwrap_or_default().trim().to_string();
+                            self.warn(path, &format!("non-string key {key} converted to a string"));
+                            key
+                        },
+                    };
+                    let value = self.yaml_value(value, &join(path, &key));
+                    map.insert(key, value);
+                }
+                Value::Object(map)
+            },
+            serde_yaml::Value::Tagged(tagged) => {
+                self.warn(path, &format!("tag {} dropped", tagged.tag));
+                self.yaml_value(tagged.value, path)
+            },
+        }
+    }
+
+    fn toml_value(&mut self, value: toml::Value, path: &str) -> Value {
+        match value {
+            toml::Value::String(s) => Value::String(s),
+            toml::Value::Integer(i) => Value::from(i),
+            toml::Value::Float(f) => Number::from_f64(f).map(Value::Number).unwrap_or_else(|| {
+                self.warn(path, &format!("{f} is not a finite number, replaced with null"));
+                Value::Null
+            }),
+            toml::Value::Boolean(b) => Value::Bool(b),
+            toml::Value::Datetime(datetime) => {
+                self.warn(path, "datetime converted to a string");
+                Value::String(datetime.to_string())
+            },
+            toml::Value::Array(items) => Value::Array(items.into_iter()
+                .enumerate()
+                .map(|(i, item)| self.toml_value(item, &format!("{path}[{i}]")))
+                .collect()),
+            toml::Value::Table(table) => Value::Object(table.into_iter()
+                .map(|(key, value)| {
+                    let value = self.toml_value(value, &join(path, &key));
+                    (key, value)
+                })
+                .collect()),
+        }
+    }
+
+    // TOML has no null, so nulls are left out.
+    fn strip_nulls(&mut self, value: Value, path: &str) -> Option<Value> {
+        match value {
+            Value::Null => {
+                self.warn(path, "null dropped");
+                None
+            },
+            Value::Array(items) => Some(Value::Array(items.into_iter()
+                .enumerate()
+                .filter_map(|(i, item)| self.strip_nulls(item, &format!("{path}[{i}]")))
+                .collect())),
+            Value::Object(map) => Some(Value::Object(map.into_iter()
+                .filter_map(|(key, value)| {
+                    let value = self.strip_nulls(value, &join(path, &key))?;
+                    Some((key, value))
+                })
+                .collect())),
+            value => Some(value),
+        }
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() { key.to_string() } else { format!("{path}.{key}") }
+}
+
+pub fn convert(input: &str, from: Format, to: Format) -> Result<Conversion, FormatError> {
+    let mut converter = Converter { warnings: Vec::new() };
+    let value = match from {
+        Format::Toml => {
+            let value = toml::from_str::<toml::Value>(input).map_err(|e| FormatError::Parse(from, e.message().to_string()))?;
+            converter.toml_value(value, "")
+        },
+        Format::Yaml => {
+            let value = serde_yaml::from_str::<serde_yaml::Value>(input).map_err(|e| FormatError::Parse(from, e.to_string()))?;
+            converter.yaml_value(value, "")
+        },
+        Format::Json => serde_json::from_str::<Value>(input).map_err(|e| FormatError::Parse(from, e.to_string()))?,
+    };
+    // The input is valid, and nothing is lost by keeping it as it is.
+    if from == to {
+        return Ok(Conversion { output: input.to_string(), warnings: Vec::new() });
+    }
+
+    let output = match to {
+        Format::Toml => {
+            let Some(value @ Value::Object(_)) = converter.strip_nulls(value, "") else {
+                return Err(FormatError::NotATable);
+            };
+            toml::to_string(&value).map_err(|e| FormatError::Serialize(to, e.to_string()))?
+        },
+        Format::Yaml => serde_yaml::to_string(&value).map_err(|e| FormatError::Serialize(to, e.to_string()))?,
+        Format::Json => serde_json::to_string_pretty(&value).map_err(|e| FormatError::Serialize(to, e.to_string()))? + "\n",
+    };
+    Ok(Conversion { output, warnings: converter.warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_datetimes_become_strings() {
+        let conversion = convert("released = 2024-12-24T00:00:00Z\n", Format::Toml, Format::Json).unwrap();
+        assert_eq!(conversion.output, "{\n  \"released\": \"2024-12-24T00:00:00Z\"\n}\n");
+        assert_eq!(conversion.warnings, ["released: datetime converted to a string"]);
+    }
+
+    #[test]
+    fn yaml_loses_nulls_tags_and_keys_in_toml() {
+        let conversion = convert("gift: ~\nsize: !big 3\n1: one\n", Format::Yaml, Format::Toml).unwrap();
+        assert_eq!(conversion.output, "size = 3\n1 = \"one\"\n");
+        assert_eq!(conversion.warnings, [
+            "size: tag !big dropped",
+            "document: non-string key 1 converted to a string",
+            "gift: null dropped",
+        ]);
+    }
+
+    #[test]
+    fn same_format_is_validated() {
+        assert!(matches!(convert("{\"a\": ", Format::Json, Format::Json), Err(FormatError::Parse(Format::Json, _))));
+        let conversion = convert("a = 1 # kept\n", Format::Toml, Format::Toml).unwrap();
+        assert_eq!(conversion.output, "a = 1 # kept\n");
+        assert!(conversion.warnings.is_empty());
+    }
+
+    #[test]
+    fn toml_needs_a_table() {
+        assert!(matches!(convert("[1, 2]", Format::Json, Format::Toml), Err(FormatError::NotATable)));
+        assert!(matches!(convert("null", Format::Json, Format::Toml), Err(FormatError::NotATable)));
+    }
+}
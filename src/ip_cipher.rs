@@ -0,0 +1,256 @@
+// Reversible transforms of IP addresses and ranges with a key of the same
+// family. They work per octet for IPv4 and per segment for IPv6, so both
+// families behave the same way.
+
+use std::fmt;
+use std::str::FromStr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use ipnet::IpNet;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    // Wrapping addition.
+    Add,
+    Xor,
+    // Left rotation by the key value, modulo the unit width.
+    Rotate,
+}
+
+impl FromStr for Algorithm {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "add" => Ok(Algorithm::Add),
+            "xor" => Ok(Algorithm::Xor),
+            "rotate" => Ok(Algorithm::Rotate),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CipherError {
+    FamilyMismatch,
+    // Addition carries into the host bits and rotation moves bits across the
+    // whole unit, so both only work on whole units.
+    UnalignedPrefix(u8),
+    NoKey,
+}
+
+impl fmt::Display for CipherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CipherError::FamilyMismatch => write!(f, "Address and key families differ"),
+            CipherError::UnalignedPrefix(prefix) => write!(f, "Prefix /{prefix} is not on a unit boundary"),
+            CipherError::NoKey => write!(f, "No key maps these addresses"),
+        }
+    }
+}
+
+impl std::error::Error for CipherError {}
+
+impl Algorithm {
+    fn encrypt(self, value: u16, key: u16, bits: u32) -> u16 {
+        let mask = width_mask(bits);
+        match self {
+            Algorithm::Add => value.wrapping_add(key) & mask,
+            Algorithm::Xor => value ^ key,
+            Algorithm::Rotate => rotate(value, key as u32 % bits, bits),
+        }
+    }
+
+    fn decrypt(self, value: u16, key: u16, bits: u32) -> u16 {
+        let mask = width_mask(bits);
+        match self {
+            Algorithm::Add => value.wrapping_sub(key) & mask,
+            Algorithm::Xor => value ^ key,
+            Algorithm::Rotate => rotate(value, bits - key as u32 % bits, bits),
+        }
+    }
+
+    // Key unit that encrypts `from` into `to`, if there is one.
+    fn key(self, from: u16, to: u16, bits: u32) -> Option<u16> {
+        match self {
+            Algorithm::Add => Some(to.wrapping_sub(from) & width_mask(bits)),
+            Algorithm::Xor => Some(from ^ to),
+            Algorithm::Rotate => (0..bits).find(|&r| rotate(from, r, bits) == to).map(|r| r as u16),
+        }
+    }
+}
+
+fn width_mask(bits: u32) -> u16 {
+    (u32::MAX >> (32 - bits)) as u16
+}
+
+// Rotates left within a unit of `bits` width.
+fn rotate(value: u16, by: u32, bits: u32) -> u16 {
+    let by = by % bits;
+    if by == 0 {
+        return value;
+    }
+    ((value << by) | (value >> (bits - by))) & width_mask(bits)
+}
+
+// Octets of an IPv4 address or segments of an IPv6 address, with their width.
+fn units(addr: IpAddr) -> (Vec<u16>, u32) {
+    match addr {
+        IpAddr::V4(addr) => (addr.octets().iter().map(|&o| o as u16).collect(), 8),
+        IpAddr::V6(addr) => (addr.segments().to_vec(), 16),
+    }
+}
+
+fn from_units(units: &[u16], like: IpAddr) -> IpAddr {
+    match like {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(units[0] as u8, units[1] as u8, units[2] as u8, units[3] as u8)),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(units[0], units[1], units[2], units[3],
+                                                  units[4], units[5], units[6], units[7])),
+    }
+}
+
+// Transforms the network bits of a range, its host bits pass through.
+// A single address is a range with a full-length prefix.
+fn transform(algo: Algorithm, op: fn(Algorithm, u16, u16, u32) -> u16, net: IpNet, key: IpAddr) -> Result<IpNet, CipherError> {
+    if net.addr().is_ipv4() != key.is_ipv4() {
+        return Err(CipherError::FamilyMismatch);
+    }
+    let (addr, bits) = units(net.addr());
+    let (mask, _) = units(net.netmask());
+    let (key, _) = units(key);
+    let full = width_mask(bits);
+    if algo != Algorithm::Xor && mask.iter().any(|&m| m != 0 && m != full) {
+        return Err(CipherError::UnalignedPrefix(net.prefix_len()));
+    }
+
+    let result = addr.iter().zip(&mask).zip(&key)
+        .map(|((&a, &m), &k)| (op(algo, a & m, k & m, bits) & m) | (a & !m))
+        .collect::<Vec<u16>>();
+    Ok(IpNet::new(from_units(&result, net.addr()), net.prefix_len()).unwrap())
+}
+
+pub fn encrypt(algo: Algorithm, from: IpNet, key: IpAddr) -> Result<IpNet, CipherError> {
+    transform(algo, Algorithm::encrypt, from, key)
+}
+
+pub fn decrypt(algo: Algorithm, to: IpNet, key: IpAddr) -> Result<IpNet, CipherError> {
+    transform(algo, Algorithm::decrypt, to, key)
+}
+
+// Key that encrypts `from` into `to`.
+pub fn key(algo: Algorithm, from: IpAddr, to: IpAddr) -> Result<IpAddr, CipherError> {
+    if from.is_ipv4() != to.is_ipv4() {
+        return Err(CipherError::FamilyMismatch);
+    }
+    let (from_parts, bits) = units(from);
+    let (to_parts, _) = units(to);
+    let key = from_parts.iter().zip(&to_parts)
+        .map(|(&f, &t)| algo.key(f, t, bits))
+        .collect::<Option<Vec<u16>>>()
+        .ok_or(CipherError::NoKey)?;
+    Ok(from_units(&key, from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [Algorithm; 3] = [Algorithm::Add, Algorithm::Xor, Algorithm::Rotate];
+
+    fn net(value: &str) -> IpNet {
+        value.parse().unwrap()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn decrypt_reverses_encrypt() {
+        let cases = [
+            ("10.0.0.1/32", "1.128.3.255"),
+            ("255.254.0.7/32", "255.255.255.255"),
+            ("fe80::1:ffff/128", "ffff:1::8000:1"),
+            ("2001:db8::dead:beef/128", "1:2:3:4:5:6:7:8"),
+        ];
+        for algo in ALGORITHMS {
+            for (from, key) in cases {
+                let encrypted = encrypt(algo, net(from), ip(key)).unwrap();
+                assert_eq!(decrypt(algo, encrypted, ip(key)).unwrap(), net(from), "{algo:?} {from} {key}");
+            }
+        }
+    }
+
+    #[test]
+    fn encrypts_known_values() {
+        assert_eq!(encrypt(Algorithm::Add, net("10.0.0.250/32"), ip("1.2.3.10")).unwrap(), net("11.2.3.4/32"));
+        assert_eq!(encrypt(Algorithm::Xor, net("10.0.0.1/32"), ip("255.0.255.1")).unwrap(), net("245.0.255.0/32"));
+        assert_eq!(encrypt(Algorithm::Rotate, net("1.128.3.0/32"), ip("1.1.9.0")).unwrap(), net("2.1.6.0/32"));
+        assert_eq!(encrypt(Algorithm::Add, net("::ffff/128"), ip("::2")).unwrap(), net("::1/128"));
+        assert_eq!(encrypt(Algorithm::Rotate, net("8000::1/128"), ip("1::4")).unwrap(), net("1::10/128"));
+    }
+
+    #[test]
+    fn key_encrypts_from_into_to() {
+        let cases = [
+            ("10.0.0.1", "11.2.3.4"),
+            ("192.168.1.255", "7.0.200.1"),
+            ("fe80::1", "::ffff:1"),
+            ("2001:db8::1", "1:2:3:4:5:6:7:8"),
+        ];
+        for algo in [Algorithm::Add, Algorithm::Xor] {
+            for (from, to) in cases {
+                let key = key(algo, ip(from), ip(to)).unwrap();
+                assert_eq!(encrypt(algo, IpNet::from(ip(from)), key).unwrap(), IpNet::from(ip(to)), "{algo:?} {from} {to}");
+            }
+        }
+    }
+
+    #[test]
+    fn rotate_key_needs_a_rotation() {
+        assert_eq!(key(Algorithm::Rotate, ip("1.128.3.0"), ip("2.1.6.0")).unwrap(), ip("1.1.1.0"));
+        assert_eq!(key(Algorithm::Rotate, ip("8000::1"), ip("1::10")).unwrap(), ip("1::4"));
+        assert!(matches!(key(Algorithm::Rotate, ip("1.0.0.0"), ip("3.0.0.0")), Err(CipherError::NoKey)));
+        assert!(matches!(key(Algorithm::Rotate, ip("::1"), ip("::3")), Err(CipherError::NoKey)));
+    }
+
+    #[test]
+    fn families_must_match() {
+        assert!(matches!(encrypt(Algorithm::Add, net("10.0.0.1/32"), ip("::1")), Err(CipherError::FamilyMismatch)));
+        assert!(matches!(key(Algorithm::Xor, ip("::1"), ip("10.0.0.1")), Err(CipherError::FamilyMismatch)));
+    }
+
+    #[test]
+    fn ranges_contain_their_encrypted_addresses() {
+        // Xor works on any prefix, the others on whole units only.
+        let cases = [
+            (Algorithm::Xor, "10.0.37.9/20", "200.100.50.25"),
+            (Algorithm::Xor, "2001:db8:abcd::1/118", "1:2:3:4::ffff"),
+            (Algorithm::Add, "10.0.33.0/24", "1.1.31.255"),
+            (Algorithm::Add, "2001:db8::ff00/112", "ffff::ff"),
+            (Algorithm::Rotate, "10.0.33.0/24", "1.1.3.7"),
+        ];
+        for (algo, from, key) in cases {
+            let range = encrypt(algo, net(from), ip(key)).unwrap();
+            for address in net(from).hosts() {
+                let encrypted = encrypt(algo, IpNet::from(address), ip(key)).unwrap();
+                assert!(range.contains(&encrypted.addr()), "{algo:?} {from} {key}: {address} -> {encrypted} not in {range}");
+            }
+        }
+    }
+
+    #[test]
+    fn add_needs_whole_units() {
+        assert!(matches!(encrypt(Algorithm::Add, net("10.0.33.0/20"), ip("1.1.31.255")), Err(CipherError::UnalignedPrefix(20))));
+        assert!(matches!(decrypt(Algorithm::Add, net("2001:db8::/36"), ip("1::")), Err(CipherError::UnalignedPrefix(36))));
+    }
+
+    #[test]
+    fn rotate_needs_whole_units() {
+        assert!(matches!(encrypt(Algorithm::Rotate, net("10.0.0.0/20"), ip("1.1.1.1")), Err(CipherError::UnalignedPrefix(20))));
+        assert!(matches!(decrypt(Algorithm::Rotate, net("2001:db8::/36"), ip("1::")), Err(CipherError::UnalignedPrefix(36))));
+        assert_eq!(encrypt(Algorithm::Rotate, net("1.128.0.0/16"), ip("1.1.1.1")).unwrap(), net("2.1.0.0/16"));
+    }
+}
@@ -0,0 +1,343 @@
+// Cargo.lock parsing and dependency analysis. Dependencies are resolved to the
+// packages they point at, the way Cargo encodes them: `name`, `name version` or
+// `name version (source)`, whichever is unambiguous.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct Lockfile {
+    pub version: Option<u32>,
+    // Required, a lockfile lists at least the package it belongs to.
+    pub package: Vec<Package>,
+    // Version 1 lockfiles keep checksums here, keyed `checksum name version (source)`.
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    Registry,
+    Git,
+    // No source: workspace members and path dependencies.
+    Path,
+}
+
+impl Package {
+    pub fn source_kind(&self) -> SourceKind {
+        match self.source.as_deref() {
+            Some(source) if source.starts_with("git+") => SourceKind::Git,
+            Some(_) => SourceKind::Registry,
+            None => SourceKind::Path,
+        }
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}@{}", self.name, self.version)
+    }
+
+    pub fn semver(&self) -> Result<semver::Version, LockfileError> {
+        semver::Version::parse(&self.version).map_err(|e| LockfileError::InvalidVersion(self.id(), e.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub enum LockfileError {
+    Parse(String),
+    InvalidVersion(String, String),
+    UnresolvedDependency(String, String),
+    AmbiguousDependency(String, String),
+}
+
+impl fmt::Display for LockfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockfileError::Parse(e) => write!(f, "Invalid lockfile: {e}"),
+            LockfileError::InvalidVersion(package, e) => write!(f, "Invalid version of {package}: {e}"),
+            LockfileError::UnresolvedDependency(package, dependency) => write!(f, "Dependency {dependency} of {package} is not in the lockfile"),
+            LockfileError::AmbiguousDependency(package, dependency) => write!(f, "Dependency {dependency} of {package} matches several packages"),
+        }
+    }
+}
+
+impl std::error::Error for LockfileError {}
+
+pub fn parse(source: &str) -> Result<Lockfile, LockfileError> {
+    toml::from_str(source).map_err(|e| LockfileError::Parse(e.message().trim().to_string()))
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackageRef {
+    pub name: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl From<&Package> for PackageRef {
+    fn from(package: &Package) -> Self {
+        PackageRef { name: package.name.clone(), version: package.version.clone(), source: package.source.clone() }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Duplicate {
+    pub name: String,
+    pub versions: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub kind: SourceKind,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Analysis {
+    // Lockfile format version, absent before version 3.
+    pub version: Option<u32>,
+    pub packages: usize,
+    // Crates locked at more than one version.
+    pub duplicates: Vec<Duplicate>,
+    pub git: Vec<PackageRef>,
+    // Path packages something depends on, the others are workspace roots.
+    pub path: Vec<PackageRef>,
+    // Registry packages without a checksum, git and path packages never have one.
+    pub missing_checksums: Vec<PackageRef>,
+    pub graph: Graph,
+}
+
+impl Lockfile {
+    pub fn checksum(&self, package: &Package) -> Option<String> {
+        package.checksum.clone().or_else(|| {
+            let source = package.source.as_deref()?;
+            let key = format!("checksum {} {} ({})", package.name, package.version, source);
+            self.metadata.get(&key).filter(|checksum| *checksum != "<none>").cloned()
+        })
+    }
+
+    // Index of the package a dependency entry of `package` points at.
+    fn resolve(&self, package: &Package, dependency: &str) -> Result<usize, LockfileError> {
+        let mut parts = dependency.splitn(3, ' ');
+        let name = parts.next().unwrap_or_default();
+        let version = parts.next();
+        let source = parts.next().map(|source| source.trim_start_matches('(').trim_end_matches(')'));
+
+        let mut matches = self.package.iter().enumerate().filter(|(_, candidate)| {
+            candidate.name == name
+                && version.is_none_or(|version| candidate.version == version)
+                && source.is_none_or(|source| candidate.source.as_deref() == Some(source))
+        });
+        match (matches.next(), matches.next()) {
+            (Some((index, _)), None) => Ok(index),
+            (None, _) => Err(LockfileError::UnresolvedDependency(package.id(), dependency.to_string())),
+            (Some(_), Some(_)) => Err(LockfileError::AmbiguousDependency(package.id(), dependency.to_string())),
+        }
+    }
+
+    // Dependency edges as package indices.
+    pub fn edges(&self) -> Result<Vec<(usize, usize)>, LockfileError> {
+        let mut edges = Vec::new();
+        for (from, package) in self.package.iter().enumerate() {
+            for dependency in &package.dependencies {
+                edges.push((from, self.resolve(package, dependency)?));
+            }
+        }
+        Ok(edges)
+    }
+
+    pub fn analyze(&self) -> Result<Analysis, LockfileError> {
+        let edges = self.edges()?;
+
+        let mut versions = BTreeMap::<&str, Vec<String>>::new();
+        for package in &self.package {
+            let entry = versions.entry(&package.name).or_default();
+            if !entry.contains(&package.version) {
+                entry.push(package.version.clone());
+            }
+        }
+        let duplicates = versions.into_iter()
+            .filter(|(_, versions)| versions.len() > 1)
+            .map(|(name, versions)| Duplicate { name: name.to_string(), versions })
+            .collect();
+
+        let of_kind = |kind: SourceKind| self.package.iter().enumerate()
+            .filter(move |(_, package)| package.source_kind() == kind);
+        let git = of_kind(SourceKind::Git).map(|(_, package)| package.into()).collect();
+        let path = of_kind(SourceKind::Path)
+            .filter(|(index, _)| edges.iter().any(|(_, to)| to == index))
+            .map(|(_, package)| package.into())
+            .collect();
+        let missing_checksums = of_kind(SourceKind::Registry)
+            .filter(|(_, package)| self.checksum(package).is_none())
+            .map(|(_, package)| package.into())
+            .collect();
+
+        let graph = Graph {
+            nodes: self.package.iter()
+                .map(|package| Node { id: package.id(), name: package.name.clone(), version: package.version.clone(), kind: package.source_kind() })
+                .collect(),
+            edges: edges.iter()
+                .map(|&(from, to)| Edge { from: self.package[from].id(), to: self.package[to].id() })
+                .collect(),
+        };
+
+        Ok(Analysis { version: self.version, packages: self.package.len(), duplicates, git, path, missing_checksums, graph })
+    }
+}
+
+// Quoted DOT string.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Analysis {
+    // Graphviz rendering of the graph. Duplicated crates are red, git and path
+    // packages dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph lockfile {\n");
+        for node in &self.graph.nodes {
+            let mut attributes = vec![format!("label={}", quote(&format!("{} {}", node.name, node.version)))];
+            if self.duplicates.iter().any(|duplicate| duplicate.name == node.name) {
+                attributes.push("color=red".to_string());
+            }
+            if node.kind != SourceKind::Registry {
+                attributes.push("style=dashed".to_string());
+            }
+            dot += &format!("    {} [{}];\n", quote(&node.id), attributes.join(", "));
+        }
+        for edge in &self.graph.edges {
+            dot += &format!("    {} -> {};\n", quote(&edge.from), quote(&edge.to));
+        }
+        dot + "}\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTRY: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+    fn lockfile(packages: &str) -> Lockfile {
+        parse(&format!("version = 4\n{packages}")).unwrap()
+    }
+
+    #[test]
+    fn dependencies_resolve_by_name_version_and_source() {
+        let lockfile = lockfile(&format!(r#"
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["rand 0.7.3", "rand 0.8.5 (git+https://example.com/rand)", "serde"]
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+source = "{REGISTRY}"
+checksum = "abc"
+
+[[package]]
+name = "rand"
+version = "0.8.5"
+source = "git+https://example.com/rand"
+
+[[package]]
+name = "rand"
+version = "0.8.5"
+source = "{REGISTRY}"
+
+[[package]]
+name = "serde"
+version = "1.0.0"
+source = "{REGISTRY}"
+"#));
+        assert_eq!(lockfile.edges().unwrap(), [(0, 1), (0, 2), (0, 4)]);
+
+        let analysis = lockfile.analyze().unwrap();
+        assert_eq!(analysis.duplicates.len(), 1);
+        assert_eq!(analysis.duplicates[0].versions, ["0.7.3", "0.8.5"]);
+        assert_eq!(analysis.git.len(), 1);
+        // The root is not depended on, so it is not listed as a path dependency.
+        assert!(analysis.path.is_empty());
+        let missing: Vec<_> = analysis.missing_checksums.iter().map(|package| package.name.as_str()).collect();
+        assert_eq!(missing, ["rand", "serde"]);
+    }
+
+    #[test]
+    fn dependencies_must_match_exactly_one_package() {
+        let lockfile = lockfile(r#"
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["rand"]
+
+[[package]]
+name = "rand"
+version = "0.7.3"
+
+[[package]]
+name = "rand"
+version = "0.8.5"
+"#);
+        assert!(matches!(lockfile.edges(), Err(LockfileError::AmbiguousDependency(..))));
+
+        let lockfile = self::lockfile("[[package]]\nname = \"app\"\nversion = \"0.1.0\"\ndependencies = [\"serde\"]\n");
+        assert!(matches!(lockfile.edges(), Err(LockfileError::UnresolvedDependency(..))));
+    }
+
+    #[test]
+    fn version_1_checksums_are_read_from_metadata() {
+        let lockfile = parse(&format!(r#"
+[[package]]
+name = "rand"
+version = "0.8.5"
+source = "{REGISTRY}"
+
+[[package]]
+name = "serde"
+version = "1.0.0"
+source = "{REGISTRY}"
+
+[metadata]
+"checksum rand 0.8.5 ({REGISTRY})" = "abc"
+"checksum serde 1.0.0 ({REGISTRY})" = "<none>"
+"#)).unwrap();
+        assert_eq!(lockfile.checksum(&lockfile.package[0]).as_deref(), Some("abc"));
+        assert_eq!(lockfile.checksum(&lockfile.package[1]), None);
+    }
+
+    #[test]
+    fn dot_output_is_quoted() {
+        let lockfile = lockfile("[[package]]\nname = \"a\\\"b\"\nversion = \"1.0.0\"\n");
+        let dot = lockfile.analyze().unwrap().to_dot();
+        assert_eq!(dot, "digraph lockfile {\n    \"a\\\"b@1.0.0\" [label=\"a\\\"b 1.0.0\", style=dashed];\n}\n");
+    }
+}
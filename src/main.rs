@@ -1,20 +1,48 @@
+mod advisories;
 mod challenges;
+mod formats;
+mod ip_cipher;
+mod lockfile;
+mod manifest_lint;
+mod manifest_policy;
+mod rate_limit;
+mod units;
 
+use std::net::SocketAddr;
+use std::time::Duration;
 use axum::{
     routing::get,
     Router
 };
 use sqlx::PgPool;
 use shuttle_runtime::CustomError;
+use advisories::AdvisoryDb;
+use manifest_policy::Policy;
+use rate_limit::{client_key, RateLimitConfig, RateLimitLayer};
 
 async fn hello_world() -> &'static str {
     "Hello, bird!"
 }
 
+// Serves the router with the peer address of each connection, which the rate
+// limits need to tell clients apart. `shuttle_axum` serves without it.
+struct AppService(Router);
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for AppService {
+    async fn bind(mut self, addr: SocketAddr) -> Result<(), shuttle_runtime::Error> {
+        let listener = tokio::net::TcpListener::bind(addr).await.map_err(CustomError::new)?;
+        axum::serve(listener, self.0.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .map_err(CustomError::new)?;
+        Ok(())
+    }
+}
+
 #[shuttle_runtime::main]
 async fn main(
     #[shuttle_shared_db::Postgres] pool: PgPool,
-) -> shuttle_axum::ShuttleAxum {
+) -> Result<AppService, shuttle_runtime::Error> {
     
     dotenv::dotenv().ok();
     
@@ -24,17 +52,34 @@ async fn main(
         .await
         .map_err(CustomError::new)?;
     
+    // Rate limits for the routes that need one.
+    let milk_limit = RateLimitLayer::new(
+        RateLimitConfig::new("MILK_BUCKET", 5, Duration::from_secs(1), client_key)
+            .message("No milk available\n")
+            .with_env(),
+        pool.clone());
+    let wrap_limit = RateLimitLayer::new(
+        RateLimitConfig::new("WRAP_BUCKET", 20, Duration::from_secs(1), client_key)
+            .refill(10)
+            .with_env(),
+        pool.clone());
+    let draft_limit = RateLimitLayer::new(
+        RateLimitConfig::new("DRAFT_BUCKET", 20, Duration::from_secs(1), client_key)
+            .refill(10)
+            .with_env(),
+        pool.clone());
+
     // Merge routes from all the challenges. 
     let router = Router::new()
         .route("/", get(hello_world))
         .merge(challenges::challenge0::get_routes())
         .merge(challenges::challenge2::get_routes())
-        .merge(challenges::challenge5::get_routes())
-        .merge(challenges::challenge9::get_routes())
+        .merge(challenges::challenge5::get_routes(pool.clone(), Policy::from_env()))
+        .merge(challenges::challenge9::get_routes(pool.clone(), milk_limit, std::env::var("BUCKET_ADMIN_TOKEN").ok()))
         .merge(challenges::challenge12::get_routes())
-        .merge(challenges::challenge16::get_routes())
-        .merge(challenges::challenge19::get_routes(pool))
-        .merge(challenges::challenge23::get_routes());
+        .merge(challenges::challenge16::get_routes(wrap_limit))
+        .merge(challenges::challenge19::get_routes(pool, draft_limit))
+        .merge(challenges::challenge23::get_routes(AdvisoryDb::from_env()));
 
-    Ok(router.into())
+    Ok(AppService(router))
 }
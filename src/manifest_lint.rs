@@ -0,0 +1,200 @@
+// Lints a Cargo manifest against the rules crates.io applies on publish,
+// collecting every problem with its position instead of stopping at the first.
+
+use std::ops::Range;
+
+use serde::Serialize;
+use toml_edit::{ImDocument, Item, TableLike};
+
+const EDITIONS: &[&str] = &["2015", "2018", "2021", "2024"];
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_KEYWORDS: usize = 5;
+const MAX_KEYWORD_LENGTH: usize = 20;
+const MAX_CATEGORIES: usize = 5;
+
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    pub field: Option<String>,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+struct Linter<'a> {
+    source: &'a str,
+    problems: Vec<Problem>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, field: Option<String>, span: Option<Range<usize>>, message: String) {
+        let (line, column) = match span {
+            Some(span) => {
+                let (line, column) = position(self.source, span.start);
+                (Some(line), Some(column))
+            },
+            None => (None, None),
+        };
+        self.problems.push(Problem { field, message, line, column });
+    }
+
+    fn check_package(&mut self, package: &Item) {
+        match package.get("name") {
+            Some(name) => if let Some(value) = name.as_str() {
+                if let Some(message) = invalid_name(value) {
+                    self.report(Some("package.name".to_string()), name.span(), message);
+                }
+            },
+            None => self.report(Some("package.name".to_string()), package.span(), "Missing package name".to_string()),
+        }
+
+        match package.get("version") {
+            Some(version) => if let Some(value) = version.as_str() {
+                if let Err(e) = semver::Version::parse(value) {
+                    self.report(Some("package.version".to_string()), version.span(), format!("Invalid version {value}: {e}"));
+                }
+            },
+            None => self.report(Some("package.version".to_string()), package.span(), "Missing package version".to_string()),
+        }
+
+        if let Some(edition) = package.get("edition") {
+            if let Some(value) = edition.as_str() {
+                if !EDITIONS.contains(&value) {
+                    self.report(Some("package.edition".to_string()), edition.span(), format!("Unknown edition {value}"));
+                }
+            }
+        }
+
+        if let Some(keywords) = package.get("keywords").and_then(Item::as_array) {
+            if keywords.len() > MAX_KEYWORDS {
+                self.report(Some("package.keywords".to_string()), keywords.span(),
+                            format!("Too many keywords ({}), at most {MAX_KEYWORDS} are allowed", keywords.len()));
+            }
+            for keyword in keywords {
+                if let Some(message) = keyword.as_str().and_then(invalid_keyword) {
+                    self.report(Some("package.keywords".to_string()), keyword.span(), message);
+                }
+            }
+        }
+
+        if let Some(categories) = package.get("categories").and_then(Item::as_array) {
+            if categories.len() > MAX_CATEGORIES {
+                self.report(Some("package.categories".to_string()), categories.span(),
+                            format!("Too many categories ({}), at most {MAX_CATEGORIES} are allowed", categories.len()));
+            }
+        }
+    }
+
+    fn check_dependencies(&mut self, table: &str, dependencies: &dyn TableLike) {
+        for (name, dependency) in dependencies.iter() {
+            // Plain version strings or tables with a `version` key; path, git
+            // and workspace dependencies may have none.
+            let version = match dependency.as_str() {
+                Some(_) => dependency,
+                None => match dependency.get("version") {
+                    Some(version) => version,
+                    None => continue,
+                },
+            };
+            let Some(requirement) = version.as_str() else {
+                continue;
+            };
+            if let Err(e) = semver::VersionReq::parse(requirement) {
+                self.report(Some(format!("{table}.{name}")), version.span(),
+                            format!("Invalid version requirement {requirement}: {e}"));
+            }
+        }
+    }
+}
+
+// 1-based line and column of a byte offset.
+pub fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+// Crate names are ASCII letters, digits, `-` and `_`, starting with a letter.
+fn invalid_name(name: &str) -> Option<String> {
+    if name.is_empty() {
+        return Some("Package name is empty".to_string());
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Some(format!("Package name is longer than {MAX_NAME_LENGTH} characters"));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Some(format!("Package name {name} must start with a letter"));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Some(format!("Package name {name} may only contain letters, numbers, - and _"));
+    }
+    None
+}
+
+// Keywords are ASCII letters, digits, `_`, `-` and `+`, starting with a letter
+// or digit.
+fn invalid_keyword(keyword: &str) -> Option<String> {
+    if keyword.len() > MAX_KEYWORD_LENGTH {
+        return Some(format!("Keyword {keyword} is longer than {MAX_KEYWORD_LENGTH} characters"));
+    }
+    if !keyword.starts_with(|c: char| c.is_ascii_alphanumeric())
+        || !keyword.chars().all(|c| c.is_ascii_alphanumeric() || "_-+".contains(c)) {
+        return Some(format!("Keyword {keyword} must start with a letter or number and only contain letters, numbers, _, - or +"));
+    }
+    None
+}
+
+pub fn lint(source: &str) -> Vec<Problem> {
+    let mut linter = Linter { source, problems: Vec::new() };
+    let document = match ImDocument::parse(source) {
+        Ok(document) => document,
+        Err(e) => {
+            linter.report(None, e.span(), e.message().trim().to_string());
+            return linter.problems;
+        },
+    };
+
+    // Fields of the wrong type or shape.
+    if let Err(e) = toml::from_str::<cargo_manifest::Manifest>(source) {
+        linter.report(None, e.span(), e.message().trim().to_string());
+    }
+
+    match document.get("package") {
+        Some(package) => linter.check_package(package),
+        None => linter.report(Some("package".to_string()), None, "Missing [package] table".to_string()),
+    }
+
+    for table in DEPENDENCY_TABLES {
+        if let Some(dependencies) = document.get(table).and_then(Item::as_table_like) {
+            linter.check_dependencies(table, dependencies);
+        }
+    }
+    if let Some(targets) = document.get("target").and_then(Item::as_table_like) {
+        for (target, item) in targets.iter() {
+            for table in DEPENDENCY_TABLES {
+                if let Some(dependencies) = item.get(table).and_then(Item::as_table_like) {
+                    linter.check_dependencies(&format!("target.{target}.{table}"), dependencies);
+                }
+            }
+        }
+    }
+
+    linter.problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keywords_may_start_with_a_digit() {
+        for keyword in ["3d", "2fa", "no-std", "c++", "x86_64"] {
+            assert_eq!(invalid_keyword(keyword), None, "{keyword}");
+        }
+        for keyword in ["-dash", "_under", "+plus", "", "two words", "émoji"] {
+            assert!(invalid_keyword(keyword).is_some(), "{keyword}");
+        }
+        assert!(invalid_keyword(&"a".repeat(MAX_KEYWORD_LENGTH + 1)).is_some());
+    }
+}
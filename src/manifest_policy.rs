@@ -0,0 +1,281 @@
+// Release policy for Cargo manifests, read from the environment:
+//
+// * `MANIFEST_REQUIRED_KEYWORDS`: keywords every package must have ("Christmas 2024" by default).
+// * `MANIFEST_FORBIDDEN_DEPENDENCIES`: crates no package may depend on.
+// * `MANIFEST_ALLOWED_LICENSES`: SPDX identifiers a package license must be made of.
+// * `MANIFEST_MIN_RUST_VERSION`: lowest acceptable `rust-version`.
+//
+// Lists are comma-separated. Rules left unset are not checked.
+
+use cargo_manifest::{Dependency, DepsSet, Manifest, MaybeInherited, WorkspacePackage};
+use serde::Serialize;
+
+const DEFAULT_REQUIRED_KEYWORDS: &[&str] = &["Christmas 2024"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    RequiredKeywords,
+    ForbiddenDependencies,
+    AllowedLicenses,
+    MinRustVersion,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Verdict {
+    pub rule: Rule,
+    // Package name, or `None` for a virtual workspace root.
+    pub package: Option<String>,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Policy {
+    required_keywords: Vec<String>,
+    forbidden_dependencies: Vec<String>,
+    allowed_licenses: Vec<String>,
+    min_rust_version: Option<semver::Version>,
+}
+
+fn list(name: &str) -> Option<Vec<String>> {
+    let value = std::env::var(name).ok()?;
+    Some(value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from).collect())
+}
+
+// `rust-version` may leave out the minor and patch numbers.
+fn parse_rust_version(version: &str) -> Option<semver::Version> {
+    let mut parts = version.trim().split('.').collect::<Vec<&str>>();
+    if parts.is_empty() || parts.len() > 3 {
+        return None;
+    }
+    parts.resize(3, "0");
+    semver::Version::parse(&parts.join(".")).ok()
+}
+
+// Resolves a package field that may be inherited from `[workspace.package]`,
+// either in the same manifest or in the workspace root.
+fn inherited<T: Clone>(
+    manifest: &Manifest,
+    root: Option<&Manifest>,
+    field: Option<&MaybeInherited<T>>,
+    from_workspace: fn(&WorkspacePackage) -> Option<T>,
+) -> Option<T> {
+    let workspace = |manifest: &Manifest| from_workspace(manifest.workspace.as_ref()?.package.as_ref()?);
+    match field?.clone() {
+        MaybeInherited::Local(value) => Some(value),
+        _ => workspace(manifest).or_else(|| root.and_then(workspace)),
+    }
+}
+
+// Crate a dependency entry points at, which differs from its key when renamed
+// with `package`.
+fn crate_name<'a>(key: &'a str, dependency: &'a Dependency) -> &'a str {
+    match dependency {
+        Dependency::Detailed(detail) => detail.package.as_deref().unwrap_or(key),
+        _ => key,
+    }
+}
+
+// Whether a license expression only uses allowed licenses. Any alternative of an
+// `OR` may be picked, every part of an `AND` is needed, and `AND` binds tighter
+// than `OR`. A `WITH` exception only adds permissions, so it is allowed when its
+// license is, or when the whole `license WITH exception` pair is listed.
+// Expressions that do not parse are not allowed.
+fn license_allowed(expression: &str, allowed: &[String]) -> bool {
+    let expression = expression.replace('(', " ( ").replace(')', " ) ").replace('/', " OR ");
+    let mut tokens = expression.split_whitespace().peekable();
+    let allowed = license_or(&mut tokens, allowed);
+    tokens.next().is_none() && allowed == Some(true)
+}
+
+type Tokens<'a> = std::iter::Peekable<std::str::SplitWhitespace<'a>>;
+
+fn is_operator(token: Option<&&str>, operator: &str) -> bool {
+    token.is_some_and(|token| token.eq_ignore_ascii_case(operator))
+}
+
+fn license_or(tokens: &mut Tokens, allowed: &[String]) -> Option<bool> {
+    let mut result = license_and(tokens, allowed)?;
+    while is_operator(tokens.peek(), "OR") {
+        tokens.next();
+        result |= license_and(tokens, allowed)?;
+    }
+    Some(result)
+}
+
+fn license_and(tokens: &mut Tokens, allowed: &[String]) -> Option<bool> {
+    let mut result = license_term(tokens, allowed)?;
+    while is_operator(tokens.peek(), "AND") {
+        tokens.next();
+        result &= license_term(tokens, allowed)?;
+    }
+    Some(result)
+}
+
+fn license_term(tokens: &mut Tokens, allowed: &[String]) -> Option<bool> {
+    let is_allowed = |license: &str| allowed.iter().any(|allowed| allowed.eq_ignore_ascii_case(license));
+    match tokens.next()? {
+        "(" => {
+            let result = license_or(tokens, allowed)?;
+            (tokens.next()? == ")").then_some(result)
+        },
+        ")" => None,
+        token if ["AND", "OR", "WITH"].iter().any(|operator| token.eq_ignore_ascii_case(operator)) => None,
+        license if is_operator(tokens.peek(), "WITH") => {
+            tokens.next();
+            let exception = tokens.next().filter(|exception| !matches!(*exception, "(" | ")"))?;
+            Some(is_allowed(license) || is_allowed(&format!("{license} WITH {exception}")))
+        },
+        license => Some(is_allowed(license)),
+    }
+}
+
+impl Policy {
+    pub fn from_env() -> Self {
+        Policy {
+            required_keywords: list("MANIFEST_REQUIRED_KEYWORDS")
+                .unwrap_or_else(|| DEFAULT_REQUIRED_KEYWORDS.iter().map(|k| k.to_string()).collect()),
+            forbidden_dependencies: list("MANIFEST_FORBIDDEN_DEPENDENCIES").unwrap_or_default(),
+            allowed_licenses: list("MANIFEST_ALLOWED_LICENSES").unwrap_or_default(),
+            min_rust_version: std::env::var("MANIFEST_MIN_RUST_VERSION").ok().and_then(|version| {
+                let parsed = parse_rust_version(&version);
+                if parsed.is_none() {
+                    println!("ERR: Invalid MANIFEST_MIN_RUST_VERSION {version}");
+                }
+                parsed
+            }),
+        }
+    }
+
+    // Verdicts of every configured rule for a manifest of the workspace under `root`.
+    // Keywords, license and rust-version are package rules, which a virtual
+    // workspace root is not checked against.
+    pub fn evaluate(&self, manifest: &Manifest, root: Option<&Manifest>) -> Vec<Verdict> {
+        let package = manifest.package.as_ref().map(|package| package.name.clone());
+        let is_package = package.is_some();
+        let mut verdicts = Vec::new();
+        let mut verdict = |rule: Rule, passed: bool, detail: String| {
+            verdicts.push(Verdict { rule, package: package.clone(), passed, detail });
+        };
+
+        if is_package && !self.required_keywords.is_empty() {
+            let keywords = inherited(manifest, root, manifest.package.as_ref().and_then(|p| p.keywords.as_ref()),
+                                     |workspace| workspace.keywords.clone())
+                .unwrap_or_default();
+            let missing = self.required_keywords.iter()
+                .filter(|keyword| !keywords.contains(keyword))
+                .cloned()
+                .collect::<Vec<String>>();
+            let detail = if missing.is_empty() { "All required keywords present".to_string() } else { format!("Missing keywords: {}", missing.join(", ")) };
+            verdict(Rule::RequiredKeywords, missing.is_empty(), detail);
+        }
+
+        if !self.forbidden_dependencies.is_empty() {
+            let mut tables = [
+                manifest.dependencies.as_ref(),
+                manifest.dev_dependencies.as_ref(),
+                manifest.build_dependencies.as_ref(),
+                manifest.workspace.as_ref().and_then(|workspace| workspace.dependencies.as_ref()),
+            ].into_iter().flatten().collect::<Vec<&DepsSet>>();
+            // Platform-specific dependencies, from `[target.<cfg>.*dependencies]`.
+            for target in manifest.target.iter().flat_map(|targets| targets.values()) {
+                tables.extend([&target.dependencies, &target.dev_dependencies, &target.build_dependencies]);
+            }
+            let found = self.forbidden_dependencies.iter()
+                .filter(|name| tables.iter()
+                    .flat_map(|table| table.iter())
+                    .any(|(key, dependency)| crate_name(key, dependency) == name.as_str()))
+                .cloned()
+                .collect::<Vec<String>>();
+            let detail = if found.is_empty() { "No forbidden dependencies".to_string() } else { format!("Forbidden dependencies: {}", found.join(", ")) };
+            verdict(Rule::ForbiddenDependencies, found.is_empty(), detail);
+        }
+
+        if is_package && !self.allowed_licenses.is_empty() {
+            let license = inherited(manifest, root, manifest.package.as_ref().and_then(|p| p.license.as_ref()),
+                                    |workspace| workspace.license.clone());
+            match license {
+                Some(license) if license_allowed(&license, &self.allowed_licenses) => verdict(Rule::AllowedLicenses, true, format!("License {license} allowed")),
+                Some(license) => verdict(Rule::AllowedLicenses, false, format!("License {license} not allowed")),
+                None => verdict(Rule::AllowedLicenses, false, "No license".to_string()),
+            }
+        }
+
+        if let Some(minimum) = self.min_rust_version.as_ref().filter(|_| is_package) {
+            let version = inherited(manifest, root, manifest.package.as_ref().and_then(|p| p.rust_version.as_ref()),
+                                    |workspace| workspace.rust_version.clone());
+            match version.as_deref().map(|version| (version, parse_rust_version(version))) {
+                Some((version, Some(parsed))) if parsed >= *minimum => verdict(Rule::MinRustVersion, true, format!("rust-version {version} is at least {minimum}")),
+                Some((version, Some(_))) => verdict(Rule::MinRustVersion, false, format!("rust-version {version} is below {minimum}")),
+                Some((version, None)) => verdict(Rule::MinRustVersion, false, format!("Invalid rust-version {version}")),
+                None => verdict(Rule::MinRustVersion, false, "No rust-version".to_string()),
+            }
+        }
+
+        verdicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> Policy {
+        Policy {
+            required_keywords: vec!["Christmas 2024".to_string()],
+            forbidden_dependencies: Vec::new(),
+            allowed_licenses: vec!["MIT".to_string()],
+            min_rust_version: parse_rust_version("1.70"),
+        }
+    }
+
+    fn manifest(source: &str) -> Manifest {
+        toml::from_str(source).unwrap()
+    }
+
+    #[test]
+    fn virtual_root_is_not_checked_against_package_rules() {
+        let root = manifest("[workspace]\nmembers = [\"a\"]\n");
+        assert!(policy().evaluate(&root, Some(&root)).is_empty());
+    }
+
+    #[test]
+    fn members_inherit_package_fields_from_the_root() {
+        let root = manifest("[workspace]\nmembers = [\"a\"]\n[workspace.package]\nkeywords = [\"Christmas 2024\"]\nlicense = \"MIT\"\nrust-version = \"1.75\"\n");
+        let member = manifest("[package]\nname = \"a\"\nkeywords.workspace = true\nlicense.workspace = true\nrust-version.workspace = true\n");
+        let verdicts = policy().evaluate(&member, Some(&root));
+        assert_eq!(verdicts.len(), 3);
+        assert!(verdicts.iter().all(|verdict| verdict.passed), "{verdicts:?}");
+
+        let bare_root = manifest("[workspace]\nmembers = [\"a\"]\n");
+        let verdicts = policy().evaluate(&member, Some(&bare_root));
+        assert!(verdicts.iter().all(|verdict| !verdict.passed), "{verdicts:?}");
+    }
+
+    #[test]
+    fn license_expressions_respect_precedence_and_parentheses() {
+        let allowed = ["MIT".to_string(), "Apache-2.0".to_string()];
+        assert!(license_allowed("MIT OR Apache-2.0", &allowed));
+        assert!(license_allowed("MIT/GPL-3.0-only", &allowed));
+        assert!(license_allowed("MIT AND (Apache-2.0 OR GPL-3.0-only)", &allowed));
+        assert!(license_allowed("GPL-3.0-only AND MIT OR Apache-2.0", &allowed));
+        assert!(!license_allowed("(MIT OR Apache-2.0) AND GPL-3.0-only", &allowed));
+        assert!(!license_allowed("MIT AND (GPL-3.0-only OR (Apache-2.0 AND BSD-3-Clause))", &allowed));
+        assert!(license_allowed("((MIT))", &allowed));
+        assert!(!license_allowed("(MIT OR Apache-2.0", &allowed));
+        assert!(!license_allowed("MIT OR", &allowed));
+        assert!(!license_allowed("", &allowed));
+    }
+
+    #[test]
+    fn license_exceptions_follow_their_license() {
+        let allowed = ["Apache-2.0".to_string(), "GPL-2.0-only WITH Classpath-exception-2.0".to_string()];
+        assert!(license_allowed("Apache-2.0 WITH LLVM-exception", &allowed));
+        assert!(license_allowed("GPL-2.0-only WITH Classpath-exception-2.0", &allowed));
+        assert!(!license_allowed("GPL-2.0-only WITH GCC-exception-2.0", &allowed));
+        assert!(!license_allowed("GPL-2.0-only", &allowed));
+        assert!(license_allowed("(GPL-2.0-only WITH Classpath-exception-2.0 OR MIT) AND Apache-2.0", &allowed));
+        assert!(!license_allowed("Apache-2.0 WITH", &allowed));
+    }
+}
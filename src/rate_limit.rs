@@ -0,0 +1,619 @@
+// Per-client rate limiting with leaky buckets, as a tower layer.
+// Buckets live in process memory, or in Postgres when several instances
+// have to share them.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex, OnceLock, RwLock, Weak},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header::RETRY_AFTER, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use ipnet::IpNet;
+use leaky_bucket::RateLimiter;
+use serde::Serialize;
+use sqlx::PgPool;
+use tower::{Layer, Service};
+
+// Buckets idle for this long, and at least until they are full again, are
+// dropped by a sweep running as often.
+const BUCKET_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Proxies trusted when `RATE_LIMIT_TRUSTED_PROXIES` is unset: loopback and
+// private networks, where the platform proxy connects from.
+const DEFAULT_TRUSTED_PROXIES: &[&str] = &[
+    "127.0.0.0/8", "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "100.64.0.0/10",
+    "::1/128", "fc00::/7",
+];
+
+const API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+// Maps a request to the name of the bucket it draws from.
+pub type KeyExtractor = fn(&Request) -> String;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Memory,
+    Postgres,
+}
+
+#[derive(Clone, Copy)]
+pub struct RateLimitConfig {
+    name: &'static str,
+    max: usize,
+    refill: usize,
+    interval: Duration,
+    key: KeyExtractor,
+    message: &'static str,
+    backend: Backend,
+}
+
+impl RateLimitConfig {
+    // Buckets hold `max` tokens and get one back every `interval`. The name
+    // keeps the buckets of each limit apart, so it must be unique.
+    pub fn new(name: &'static str, max: usize, interval: Duration, key: KeyExtractor) -> Self {
+        Self {
+            name,
+            max,
+            refill: 1,
+            interval,
+            key,
+            message: "Too many requests\n",
+            backend: Backend::Memory,
+        }
+    }
+
+    pub fn refill(mut self, refill: usize) -> Self {
+        self.refill = refill;
+        self
+    }
+
+    // Body of the 429 response.
+    pub fn message(mut self, message: &'static str) -> Self {
+        self.message = message;
+        self
+    }
+
+    // Overrides the bucket parameters from `<NAME>_MAX`, `<NAME>_REFILL`,
+    // `<NAME>_INTERVAL_MS` and `<NAME>_BACKEND` (`memory` or `postgres`), if set.
+    pub fn with_env(mut self) -> Self {
+        let prefix = self.name;
+        if let Ok(backend) = std::env::var(format!("{prefix}_BACKEND")) {
+            match backend.to_lowercase().as_str() {
+                "memory" => self.backend = Backend::Memory,
+                "postgres" => self.backend = Backend::Postgres,
+                _ => println!("ERR: Unknown rate limit backend {backend} for {prefix}"),
+            }
+        }
+        let var = |name: &str| std::env::var(format!("{prefix}_{name}")).ok()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|value| *value > 0);
+        if let Some(max) = var("MAX") {
+            self.max = max;
+        }
+        if let Some(refill) = var("REFILL") {
+            self.refill = refill;
+        }
+        if let Some(interval) = var("INTERVAL_MS") {
+            self.interval = Duration::from_millis(interval as u64);
+        }
+        self
+    }
+
+    fn limiter(&self, initial: usize) -> RateLimiter {
+        RateLimiter::builder()
+            .interval(self.interval)
+            .refill(self.refill)
+            .max(self.max)
+            .initial(initial.min(self.max))
+            .build()
+    }
+
+    // Parameters as stored in `rate_limit_configs`: max, refill and interval in
+    // milliseconds.
+    fn set_parameters(&mut self, (max, refill, interval_ms): (i64, i64, i64)) {
+        self.max = max as usize;
+        self.refill = refill as usize;
+        self.interval = Duration::from_millis(interval_ms as u64);
+    }
+
+    // Time after which an untouched bucket is full, and can be dropped.
+    fn idle_timeout(&self) -> Duration {
+        let refills = self.max.div_ceil(self.refill.max(1)) as u32;
+        BUCKET_IDLE_TIMEOUT.max(self.interval.saturating_mul(refills))
+    }
+
+    // Tokens per second, for the Postgres buckets which refill continuously.
+    fn rate(&self) -> f64 {
+        self.refill as f64 / self.interval.as_secs_f64()
+    }
+}
+
+// Who may choose the bucket they draw from, read once from the environment:
+// `RATE_LIMIT_API_KEYS` lists the API keys clients may identify with, and
+// `RATE_LIMIT_TRUSTED_PROXIES` the proxies (addresses or CIDR ranges) whose
+// `X-Forwarded-For` is believed. Both are comma-separated.
+struct ClientIdentity {
+    api_keys: Vec<String>,
+    trusted_proxies: Vec<IpNet>,
+}
+
+static CLIENT_IDENTITY: OnceLock<ClientIdentity> = OnceLock::new();
+
+impl ClientIdentity {
+    fn from_env() -> Self {
+        let list = |value: &str| value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect::<Vec<String>>();
+        let trusted_proxies = std::env::var("RATE_LIMIT_TRUSTED_PROXIES")
+            .map(|proxies| list(&proxies))
+            .unwrap_or_else(|_| DEFAULT_TRUSTED_PROXIES.iter().map(|proxy| proxy.to_string()).collect())
+            .into_iter()
+            .filter_map(|proxy| {
+                let net = proxy.parse::<IpNet>().or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from)).ok();
+                if net.is_none() {
+                    println!("ERR: Invalid trusted proxy {proxy}");
+                }
+                net
+            })
+            .collect();
+        ClientIdentity { api_keys: list(&std::env::var("RATE_LIMIT_API_KEYS").unwrap_or_default()), trusted_proxies }
+    }
+
+    fn trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|proxy| proxy.contains(&ip))
+    }
+
+    // Walks `X-Forwarded-For` back from the peer while the hops are trusted
+    // proxies. The first hop that is not one is the client, earlier entries
+    // may have been made up by it.
+    fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let hops = headers.get_all(X_FORWARDED_FOR).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .collect::<Vec<&str>>();
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            if !self.trusted(client) {
+                break;
+            }
+            match hop.parse::<IpAddr>() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+        }
+        client
+    }
+}
+
+// Clients are identified by a known API key, then by peer IP address, or the
+// forwarded one behind a trusted proxy. The peer address is only known when the
+// router is served with connect info.
+pub fn client_key(request: &Request) -> String {
+    let identity = CLIENT_IDENTITY.get_or_init(ClientIdentity::from_env);
+    let headers = request.headers();
+    if let Some(key) = headers.get(API_KEY).and_then(|v| v.to_str().ok()) {
+        if identity.api_keys.iter().any(|known| known == key) {
+            return format!("key:{key}");
+        }
+    }
+    match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", identity.client_ip(addr.ip(), headers)),
+        None => "anonymous".to_string(),
+    }
+}
+
+struct Bucket {
+    limiter: RateLimiter,
+    last_seen: Instant,
+}
+
+impl Bucket {
+    // The limiter only refills its balance when tokens are taken, so add
+    // the refills since the last request to get the tokens available now.
+    fn tokens(&self, now: Instant) -> usize {
+        let limiter = &self.limiter;
+        let refills = now.duration_since(self.last_seen).as_millis() / limiter.interval().as_millis().max(1);
+        let refilled = (refills as usize).saturating_mul(limiter.refill());
+        limiter.balance().saturating_add(refilled).min(limiter.max())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BucketStatus {
+    pub max: usize,
+    pub refill: usize,
+    pub interval_ms: u64,
+    pub tokens: usize,
+}
+
+#[derive(Clone)]
+enum Store {
+    Memory(Arc<Mutex<HashMap<String, Bucket>>>),
+    Postgres(PgPool),
+}
+
+// Store as seen by the sweep, which must not keep the memory buckets alive.
+enum WeakStore {
+    Memory(Weak<Mutex<HashMap<String, Bucket>>>),
+    Postgres(PgPool),
+}
+
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    config: Arc<RwLock<RateLimitConfig>>,
+    store: Store,
+}
+
+impl RateLimitLayer {
+    // The pool is only used by the Postgres backend.
+    pub fn new(config: RateLimitConfig, pool: PgPool) -> Self {
+        let store = match config.backend {
+            Backend::Memory => Store::Memory(Arc::new(Mutex::new(HashMap::new()))),
+            Backend::Postgres => Store::Postgres(pool),
+        };
+        let layer = Self {
+            config: Arc::new(RwLock::new(config)),
+            store,
+        };
+        layer.spawn_sweep();
+        layer
+    }
+
+    // Drops idle buckets in the background until the layer is dropped.
+    fn spawn_sweep(&self) {
+        let config = Arc::downgrade(&self.config);
+        let store = match &self.store {
+            Store::Memory(buckets) => WeakStore::Memory(Arc::downgrade(buckets)),
+            Store::Postgres(pool) => WeakStore::Postgres(pool.clone()),
+        };
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(BUCKET_IDLE_TIMEOUT);
+            loop {
+                interval.tick().await;
+                let Some(config) = config.upgrade() else {
+                    break;
+                };
+                let config = *config.read().unwrap();
+                let idle_timeout = config.idle_timeout();
+                match &store {
+                    WeakStore::Memory(buckets) => {
+                        let Some(buckets) = buckets.upgrade() else {
+                            break;
+                        };
+                        let now = Instant::now();
+                        buckets.lock().unwrap().retain(|_, bucket| now.duration_since(bucket.last_seen) < idle_timeout);
+                    },
+                    WeakStore::Postgres(pool) => {
+                        let result = sqlx::query("DELETE FROM rate_limit_buckets WHERE starts_with(key, $1 || ':') AND updated_at < now() - make_interval(secs => $2)")
+                            .bind(config.name)
+                            .bind(idle_timeout.as_secs_f64())
+                            .execute(pool)
+                            .await;
+                        if let Err(e) = result {
+                            println!("ERR: Cannot sweep {} buckets: {e}", config.name);
+                        }
+                    },
+                }
+            }
+        });
+    }
+
+    // Empties all buckets, clients get a full one on their next request.
+    pub async fn reset(&self) {
+        let name = self.config.read().unwrap().name;
+        match &self.store {
+            Store::Memory(buckets) => buckets.lock().unwrap().clear(),
+            Store::Postgres(pool) => {
+                let result = sqlx::query("DELETE FROM rate_limit_buckets WHERE starts_with(key, $1 || ':')")
+                    .bind(name)
+                    .execute(pool)
+                    .await;
+                if let Err(e) = result {
+                    println!("ERR: Cannot reset {name} buckets: {e}");
+                }
+            },
+        }
+    }
+
+    // Parameters in effect. Postgres buckets are shared by every instance, so
+    // their parameters are too: changes made on any instance are read back from
+    // `rate_limit_configs`, and outlive restarts.
+    async fn config(&self) -> RateLimitConfig {
+        let mut config = *self.config.read().unwrap();
+        let Store::Postgres(pool) = &self.store else {
+            return config;
+        };
+        let parameters = sqlx::query_as::<_, (i64, i64, i64)>(
+                "SELECT max_tokens, refill, interval_ms FROM rate_limit_configs WHERE name = $1")
+            .bind(config.name)
+            .fetch_optional(pool)
+            .await;
+        match parameters {
+            Ok(Some(parameters)) => {
+                config.set_parameters(parameters);
+                *self.config.write().unwrap() = config;
+            },
+            Ok(None) => {},
+            Err(e) => println!("ERR: Cannot read {} bucket parameters: {e}", config.name),
+        }
+        config
+    }
+
+    // Parameters and available tokens of the bucket the request draws from.
+    pub async fn status(&self, request: Request) -> BucketStatus {
+        let config = self.config().await;
+        let key = (config.key)(&request);
+        let tokens = match &self.store {
+            Store::Memory(buckets) => buckets.lock().unwrap()
+                .get(&key)
+                .map(|bucket| bucket.tokens(Instant::now())),
+            Store::Postgres(pool) => sqlx::query_scalar::<_, f64>(
+                    "SELECT LEAST($2, tokens + EXTRACT(EPOCH FROM now() - updated_at)::float8 * $3) \
+                    FROM rate_limit_buckets WHERE key = $1")
+                .bind(format!("{}:{key}", config.name))
+                .bind(config.max as f64)
+                .bind(config.rate())
+                .fetch_optional(pool)
+                .await
+                .ok()
+                .flatten()
+                .map(|tokens| tokens.floor() as usize),
+        };
+        BucketStatus {
+            max: config.max,
+            refill: config.refill,
+            interval_ms: config.interval.as_millis() as u64,
+            tokens: tokens.unwrap_or(config.max),
+        }
+    }
+
+    // Changes the given bucket parameters. Existing buckets keep their
+    // tokens, capped at the new maximum.
+    pub async fn reconfigure(&self, max: Option<usize>, refill: Option<usize>, interval: Option<Duration>) -> Result<(), sqlx::Error> {
+        match &self.store {
+            Store::Memory(buckets) => {
+                let mut config = self.config.write().unwrap();
+                config.max = max.unwrap_or(config.max);
+                config.refill = refill.unwrap_or(config.refill);
+                config.interval = interval.unwrap_or(config.interval);
+                let now = Instant::now();
+                for bucket in buckets.lock().unwrap().values_mut() {
+                    bucket.limiter = config.limiter(bucket.tokens(now));
+                    bucket.last_seen = now;
+                }
+            },
+            // Updated in a single statement so that concurrent changes from
+            // several instances are merged. Buckets are capped on their next use.
+            Store::Postgres(pool) => {
+                let config = *self.config.read().unwrap();
+                let parameters = sqlx::query_as::<_, (i64, i64, i64)>(
+                        "INSERT INTO rate_limit_configs AS config (name, max_tokens, refill, interval_ms) \
+                        VALUES ($1, COALESCE($2, $5), COALESCE($3, $6), COALESCE($4, $7)) \
+                        ON CONFLICT (name) DO UPDATE SET \
+                            max_tokens = COALESCE($2, config.max_tokens), \
+                            refill = COALESCE($3, config.refill), \
+                            interval_ms = COALESCE($4, config.interval_ms), \
+                            updated_at = now() \
+                        RETURNING max_tokens, refill, interval_ms")
+                    .bind(config.name)
+                    .bind(max.map(|max| max as i64))
+                    .bind(refill.map(|refill| refill as i64))
+                    .bind(interval.map(|interval| interval.as_millis() as i64))
+                    .bind(config.max as i64)
+                    .bind(config.refill as i64)
+                    .bind(config.interval.as_millis() as i64)
+                    .fetch_one(pool)
+                    .await?;
+                self.config.write().unwrap().set_parameters(parameters);
+            },
+        }
+        Ok(())
+    }
+
+    // Takes one token from the bucket and returns whether it succeeded
+    // together with the rate limit headers for the response.
+    async fn acquire(&self, key: String) -> (bool, HeaderMap) {
+        let config = self.config().await;
+        let (acquired, remaining, reset) = match &self.store {
+            Store::Memory(buckets) => Self::acquire_memory(&config, buckets, key),
+            Store::Postgres(pool) => Self::acquire_postgres(&config, pool, key).await,
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(config.max));
+        headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(remaining));
+        headers.insert(X_RATELIMIT_RESET, HeaderValue::from(reset));
+        if !acquired {
+            let retry_after = config.interval.as_secs_f64().ceil() as u64;
+            headers.insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        (acquired, headers)
+    }
+
+    // Returns whether a token was taken, the tokens left and the seconds
+    // until the bucket is full again.
+    fn acquire_memory(config: &RateLimitConfig, buckets: &Mutex<HashMap<String, Bucket>>, key: String) -> (bool, usize, u64) {
+        let mut buckets = buckets.lock().unwrap();
+        let now = Instant::now();
+        let bucket = buckets.entry(key).or_insert_with(|| Bucket {
+            limiter: config.limiter(config.max),
+            last_seen: now,
+        });
+        bucket.last_seen = now;
+
+        let limiter = &bucket.limiter;
+        let acquired = limiter.try_acquire(1);
+        let remaining = limiter.balance();
+        let refills = (limiter.max() - remaining).div_ceil(limiter.refill()) as u32;
+        let reset = (limiter.interval() * refills).as_secs_f64().ceil() as u64;
+        (acquired, remaining, reset)
+    }
+
+    // Refills and takes a token in a single statement, so that concurrent
+    // requests from several instances cannot overdraw the bucket. No row is
+    // returned when the bucket is empty.
+    async fn acquire_postgres(config: &RateLimitConfig, pool: &PgPool, key: String) -> (bool, usize, u64) {
+        let tokens = sqlx::query_scalar::<_, f64>(
+                "INSERT INTO rate_limit_buckets AS bucket (key, tokens, updated_at) \
+                VALUES ($1, $2 - 1, now()) \
+                ON CONFLICT (key) DO UPDATE SET \
+                    tokens = LEAST($2, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::float8 * $3) - 1, \
+                    updated_at = now() \
+                WHERE LEAST($2, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::float8 * $3) >= 1 \
+                RETURNING tokens")
+            .bind(format!("{}:{key}", config.name))
+            .bind(config.max as f64)
+            .bind(config.rate())
+            .fetch_optional(pool)
+            .await;
+        match tokens {
+            Ok(Some(tokens)) => {
+                let reset = ((config.max as f64 - tokens) / config.rate()).ceil() as u64;
+                (true, tokens.floor() as usize, reset)
+            },
+            Ok(None) => (false, 0, (config.max as f64 / config.rate()).ceil() as u64),
+            // Let requests through rather than failing them when the database is down.
+            Err(e) => {
+                println!("ERR: Rate limit backend unavailable: {e}");
+                (true, config.max, 0)
+            },
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S> Service<Request> for RateLimit<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // The inner service was driven to readiness, so keep that one and
+        // leave a fresh clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            let config = *layer.config.read().unwrap();
+            let key = (config.key)(&request);
+            let (acquired, headers) = layer.acquire(key).await;
+            if !acquired {
+                return Ok((StatusCode::TOO_MANY_REQUESTS, headers, config.message).into_response());
+            }
+
+            let mut response = inner.call(request).await?;
+            response.headers_mut().extend(headers);
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use axum::body::Body;
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    fn identity(trusted_proxies: &[&str]) -> ClientIdentity {
+        ClientIdentity {
+            api_keys: Vec::new(),
+            trusted_proxies: trusted_proxies.iter().map(|proxy| proxy.parse().unwrap()).collect(),
+        }
+    }
+
+    fn request(peer: &str, forwarded_for: Option<&str>) -> Request {
+        let mut request = Request::new(Body::empty());
+        request.extensions_mut().insert(ConnectInfo(SocketAddr::new(peer.parse().unwrap(), 443)));
+        if let Some(forwarded_for) = forwarded_for {
+            request.headers_mut().insert(X_FORWARDED_FOR, HeaderValue::from_str(forwarded_for).unwrap());
+        }
+        request
+    }
+
+    async fn status(limit: &RateLimitLayer, request: Request) -> StatusCode {
+        let service = limit.layer(service_fn(|_: Request| async { Ok::<_, Infallible>(StatusCode::OK.into_response()) }));
+        service.oneshot(request).await.unwrap().status()
+    }
+
+    #[test]
+    fn forwarded_for_is_only_believed_from_trusted_proxies() {
+        let identity = identity(&["10.0.0.0/8"]);
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_static("192.0.2.7, 198.51.100.1, 10.0.0.3"));
+
+        assert_eq!(identity.client_ip("203.0.113.9".parse().unwrap(), &headers), "203.0.113.9".parse::<IpAddr>().unwrap());
+        assert_eq!(identity.client_ip("10.0.0.2".parse().unwrap(), &headers), "198.51.100.1".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn platform_proxy_is_trusted_by_default() {
+        let identity = identity(DEFAULT_TRUSTED_PROXIES);
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_static("198.51.100.1"));
+
+        assert_eq!(identity.client_ip("10.1.2.3".parse().unwrap(), &headers), "198.51.100.1".parse::<IpAddr>().unwrap());
+        assert_eq!(identity.client_ip("fd00::1".parse().unwrap(), &headers), "198.51.100.1".parse::<IpAddr>().unwrap());
+        assert_eq!(identity.client_ip("203.0.113.9".parse().unwrap(), &headers), "203.0.113.9".parse::<IpAddr>().unwrap());
+    }
+
+    #[tokio::test]
+    async fn clients_get_separate_buckets() {
+        let pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let limit = RateLimitLayer::new(RateLimitConfig::new("TEST", 1, Duration::from_secs(60), client_key), pool);
+
+        assert_eq!(status(&limit, request("203.0.113.1", None)).await, StatusCode::OK);
+        assert_eq!(status(&limit, request("203.0.113.1", None)).await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status(&limit, request("203.0.113.2", None)).await, StatusCode::OK);
+        assert_eq!(status(&limit, request("203.0.113.2", None)).await, StatusCode::TOO_MANY_REQUESTS);
+
+        // Behind the platform proxy, clients differ by their forwarded address.
+        assert_eq!(status(&limit, request("10.0.0.1", Some("198.51.100.1"))).await, StatusCode::OK);
+        assert_eq!(status(&limit, request("10.0.0.1", Some("198.51.100.2"))).await, StatusCode::OK);
+        assert_eq!(status(&limit, request("10.0.0.1", Some("198.51.100.1"))).await, StatusCode::TOO_MANY_REQUESTS);
+    }
+}
@@ -0,0 +1,201 @@
+// Unit conversion for volume, mass and temperature.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Volume,
+    Mass,
+    Temperature,
+}
+
+// Values are converted through the base unit of their dimension
+// (liter, kilogram, kelvin) as `base = value * factor + offset`.
+#[derive(Debug, Clone, Copy)]
+pub struct Unit {
+    pub dimension: Dimension,
+    factor: f64,
+    offset: f64,
+}
+
+// Name, dimension, factor, offset and whether SI prefixes apply.
+const UNITS: &[(&str, Dimension, f64, f64, bool)] = &[
+    // Volume, in liters.
+    ("l", Dimension::Volume, 1.0, 0.0, true),
+    ("L", Dimension::Volume, 1.0, 0.0, true),
+    ("liter", Dimension::Volume, 1.0, 0.0, true),
+    ("liters", Dimension::Volume, 1.0, 0.0, true),
+    ("litre", Dimension::Volume, 1.0, 0.0, true),
+    ("litres", Dimension::Volume, 1.0, 0.0, true),
+    ("m3", Dimension::Volume, 1000.0, 0.0, false),
+    // US customary.
+    ("gal", Dimension::Volume, 3.785411784, 0.0, false),
+    ("gallon", Dimension::Volume, 3.785411784, 0.0, false),
+    ("gallons", Dimension::Volume, 3.785411784, 0.0, false),
+    ("quart", Dimension::Volume, 0.946352946, 0.0, false),
+    ("quarts", Dimension::Volume, 0.946352946, 0.0, false),
+    ("us_pint", Dimension::Volume, 0.473176473, 0.0, false),
+    ("us_pints", Dimension::Volume, 0.473176473, 0.0, false),
+    ("cup", Dimension::Volume, 0.2365882365, 0.0, false),
+    ("cups", Dimension::Volume, 0.2365882365, 0.0, false),
+    ("fl_oz", Dimension::Volume, 0.0295735295625, 0.0, false),
+    // Imperial.
+    ("uk_gallon", Dimension::Volume, 4.54609, 0.0, false),
+    ("uk_gallons", Dimension::Volume, 4.54609, 0.0, false),
+    ("pint", Dimension::Volume, 0.56826125, 0.0, false),
+    ("pints", Dimension::Volume, 0.56826125, 0.0, false),
+    // Mass, in kilograms.
+    ("g", Dimension::Mass, 0.001, 0.0, true),
+    ("gram", Dimension::Mass, 0.001, 0.0, true),
+    ("grams", Dimension::Mass, 0.001, 0.0, true),
+    ("t", Dimension::Mass, 1000.0, 0.0, false),
+    ("tonne", Dimension::Mass, 1000.0, 0.0, false),
+    ("tonnes", Dimension::Mass, 1000.0, 0.0, false),
+    ("lb", Dimension::Mass, 0.45359237, 0.0, false),
+    ("pound", Dimension::Mass, 0.45359237, 0.0, false),
+    ("pounds", Dimension::Mass, 0.45359237, 0.0, false),
+    ("oz", Dimension::Mass, 0.028349523125, 0.0, false),
+    ("ounce", Dimension::Mass, 0.028349523125, 0.0, false),
+    ("ounces", Dimension::Mass, 0.028349523125, 0.0, false),
+    // Temperature, in kelvin.
+    ("K", Dimension::Temperature, 1.0, 0.0, false),
+    ("kelvin", Dimension::Temperature, 1.0, 0.0, false),
+    ("C", Dimension::Temperature, 1.0, 273.15, false),
+    ("celsius", Dimension::Temperature, 1.0, 273.15, false),
+    ("F", Dimension::Temperature, 5.0 / 9.0, 273.15 - 32.0 * 5.0 / 9.0, false),
+    ("fahrenheit", Dimension::Temperature, 5.0 / 9.0, 273.15 - 32.0 * 5.0 / 9.0, false),
+];
+
+// Symbols and names of SI prefixes, longest first so that `da` wins over `d`.
+const PREFIXES: &[(&str, f64)] = &[
+    ("hecto", 1e2), ("micro", 1e-6), ("milli", 1e-3), ("centi", 1e-2),
+    ("kilo", 1e3), ("mega", 1e6), ("giga", 1e9), ("nano", 1e-9),
+    ("deca", 1e1), ("deci", 1e-1),
+    ("da", 1e1),
+    ("G", 1e9), ("M", 1e6), ("k", 1e3), ("h", 1e2), ("d", 1e-1),
+    ("c", 1e-2), ("m", 1e-3), ("u", 1e-6), ("µ", 1e-6), ("n", 1e-9),
+];
+
+// Rounding beyond this many decimals is meaningless for an f64.
+pub const MAX_PRECISION: u32 = 15;
+
+#[derive(Debug)]
+pub enum ConversionError {
+    UnknownUnit(String),
+    IncompatibleUnits(String, String),
+    InvalidValue(f64),
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::UnknownUnit(unit) => write!(f, "Unknown unit {unit}"),
+            ConversionError::IncompatibleUnits(from, to) => write!(f, "Cannot convert {from} to {to}"),
+            ConversionError::InvalidValue(value) => write!(f, "Invalid value {value}"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+impl Unit {
+    // Looks up a unit by symbol or name, with an optional SI prefix.
+    pub fn parse(name: &str) -> Result<Unit, ConversionError> {
+        let lookup = |name: &str| UNITS.iter()
+            .find(|(unit, ..)| *unit == name)
+            .or_else(|| UNITS.iter().find(|(unit, ..)| unit.len() > 1 && unit.eq_ignore_ascii_case(name)));
+
+        if let Some(&(_, dimension, factor, offset, _)) = lookup(name) {
+            return Ok(Unit { dimension, factor, offset });
+        }
+        for (prefix, scale) in PREFIXES {
+            let Some(rest) = name.strip_prefix(prefix) else {
+                continue;
+            };
+            if let Some(&(_, dimension, factor, offset, true)) = lookup(rest) {
+                return Ok(Unit { dimension, factor: factor * scale, offset });
+            }
+        }
+        Err(ConversionError::UnknownUnit(name.to_string()))
+    }
+}
+
+pub fn convert(value: f64, from: &str, to: &str) -> Result<f64, ConversionError> {
+    let source = Unit::parse(from)?;
+    let target = Unit::parse(to)?;
+    if source.dimension != target.dimension {
+        return Err(ConversionError::IncompatibleUnits(from.to_string(), to.to_string()));
+    }
+    let base = value * source.factor + source.offset;
+    // Quantities cannot be negative and temperatures cannot go below absolute zero.
+    if !base.is_finite() || base < 0.0 {
+        return Err(ConversionError::InvalidValue(value));
+    }
+    Ok((base - target.offset) / target.factor)
+}
+
+// Rounds to the given number of decimals.
+pub fn round(value: f64, precision: u32) -> f64 {
+    let scale = 10f64.powi(precision.min(MAX_PRECISION) as i32);
+    (value * scale).round() / scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn prefixes_apply_to_metric_units() {
+        assert_close(convert(1.0, "kg", "g").unwrap(), 1000.0);
+        assert_close(convert(250.0, "ml", "l").unwrap(), 0.25);
+        assert_close(convert(1.0, "dal", "dl").unwrap(), 100.0);
+        assert_close(convert(2.0, "kilogram", "grams").unwrap(), 2000.0);
+        assert_close(convert(3.0, "µg", "ng").unwrap(), 3000.0);
+    }
+
+    #[test]
+    fn prefixes_do_not_apply_to_other_units() {
+        assert!(matches!(Unit::parse("kgal"), Err(ConversionError::UnknownUnit(_))));
+        assert!(matches!(Unit::parse("kK"), Err(ConversionError::UnknownUnit(_))));
+        assert!(matches!(Unit::parse("km3"), Err(ConversionError::UnknownUnit(_))));
+    }
+
+    #[test]
+    fn names_ignore_case_but_symbols_do_not() {
+        assert_close(convert(1.0, "Gallons", "LITERS").unwrap(), 3.785411784);
+        // `M` is mega, `m` milli.
+        assert_close(convert(1.0, "Mg", "mg").unwrap(), 1e9);
+    }
+
+    #[test]
+    fn temperatures_use_offsets() {
+        assert_close(convert(0.0, "C", "F").unwrap(), 32.0);
+        assert_close(convert(-40.0, "celsius", "fahrenheit").unwrap(), -40.0);
+        assert_close(convert(212.0, "F", "K").unwrap(), 373.15);
+        assert_close(convert(0.0, "K", "C").unwrap(), -273.15);
+    }
+
+    #[test]
+    fn values_below_zero_are_invalid() {
+        assert!(matches!(convert(-1.0, "l", "ml"), Err(ConversionError::InvalidValue(_))));
+        assert!(matches!(convert(-300.0, "C", "K"), Err(ConversionError::InvalidValue(_))));
+        assert!(matches!(convert(f64::INFINITY, "g", "kg"), Err(ConversionError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn dimensions_must_match() {
+        assert!(matches!(convert(1.0, "l", "kg"), Err(ConversionError::IncompatibleUnits(_, _))));
+        assert!(matches!(convert(1.0, "C", "g"), Err(ConversionError::IncompatibleUnits(_, _))));
+    }
+
+    #[test]
+    fn rounds_to_precision() {
+        assert_eq!(round(1.23456, 2), 1.23);
+        assert_eq!(round(2.5, 0), 3.0);
+        assert_eq!(round(1.0 / 3.0, 40), round(1.0 / 3.0, MAX_PRECISION));
+    }
+}